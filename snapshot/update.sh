@@ -0,0 +1,20 @@
+#!/bin/sh
+# Vendor the parsed database of the latest revision of the Wikipedia page for
+# the `bundled-snapshot` feature.
+#
+# Run this again whenever the parsed database format changes.
+# Mirrors can be used by setting `WTP_HISTORY_API_URL` and `WTP_PAGE_URL`.
+set -eu
+
+snapshot_dir=$(dirname "$(realpath "$0")")
+cache_dir=$(mktemp -d)
+trap 'rm -rf "$cache_dir"' EXIT
+
+# any query will do; we only need the page to be parsed and cached
+cargo run --quiet --manifest-path "$snapshot_dir/../Cargo.toml" -- \
+    --pull --cache-dir "$cache_dir" --source wikipedia 0 >/dev/null
+
+database=$(find "$cache_dir" -name '*.parsed-*.json' | head -n 1)
+revision=$(basename "$database" | cut -d . -f 1)
+gzip -9 -n -c "$database" >"$snapshot_dir/database.json.gz"
+echo "Vendored revision $revision"
@@ -0,0 +1,269 @@
+//! Finding port numbers in free-form text, e.g. log lines.
+
+use std::{net::IpAddr, sync::LazyLock};
+
+use itertools::Itertools;
+use regex::Regex;
+
+use crate::{
+    query::{PortSelection, SupportedProtocol},
+    store::{ServiceIndex, ServicePort},
+};
+
+/// Find all ports mentioned in a line of `ss`, `netstat`, `lsof -i` or `nmap` output.
+///
+/// Recognised forms:
+/// - `addr:port`, e.g. `0.0.0.0:22`, `[::]:80`, `*:443`, `:::22`, `localhost:8080`
+/// - `addr.port` as printed by BSD `netstat`, e.g. `127.0.0.1.631`, `*.22`
+/// - `port/proto` as printed by `nmap`, e.g. `22/tcp`
+///
+/// Service names in place of port numbers (e.g. `*:ssh` by `lsof`) are
+/// resolved using the service index. Wildcard ports (e.g. `0.0.0.0:*`) are
+/// ignored.
+pub fn find_ports(line: &str, service_index: &ServiceIndex) -> Vec<PortSelection> {
+    static NMAP_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^\d{1,5}/(tcp|udp|sctp)$").unwrap());
+
+    let protocol = detect_protocol(line);
+
+    line.split_whitespace()
+        // `lsof` shows connections as `local->remote`
+        .flat_map(|token| token.split("->"))
+        .filter_map(|token| {
+            if NMAP_REGEX.is_match(token) {
+                return token.parse().ok();
+            }
+            let (addr, port) = split_address(token)?;
+            if !is_address(addr) {
+                return None;
+            }
+            let number = match port.parse() {
+                Ok(n) => n,
+                Err(_) => resolve_service(port, protocol, service_index)?,
+            };
+            Some(PortSelection { number, protocol })
+        })
+        .unique()
+        .collect()
+}
+
+/// Detect the protocol of a line.
+///
+/// `ss` and `netstat` print it in the first column (e.g. `tcp`, `udp6`);
+/// `lsof` prints it as a separate uppercase column (e.g. `TCP`).
+fn detect_protocol(line: &str) -> SupportedProtocol {
+    use SupportedProtocol as P;
+
+    let first = line.split_whitespace().next().unwrap_or_default();
+    let first = first.to_lowercase();
+    if first.starts_with("tcp") {
+        return P::Tcp;
+    }
+    if first.starts_with("udp") {
+        return P::Udp;
+    }
+    if first.starts_with("sctp") {
+        return P::Sctp;
+    }
+
+    for token in line.split_whitespace() {
+        match token {
+            "TCP" => return P::Tcp,
+            "UDP" => return P::Udp,
+            "SCTP" => return P::Sctp,
+            _ => {}
+        }
+    }
+    P::Any
+}
+
+/// Split a token into its address and port parts.
+fn split_address(token: &str) -> Option<(&str, &str)> {
+    // strip trailing punctuation, e.g. `127.0.0.1:8080,`
+    let token = token.trim_end_matches([',', ';', ')']);
+    let token = token.trim_start_matches('(');
+
+    if let Some(split) = token.rsplit_once(':') {
+        return Some(split);
+    }
+    // BSD `netstat` separates the port with a dot
+    let (addr, port) = token.rsplit_once('.')?;
+    (addr == "*" || addr.parse::<IpAddr>().is_ok()).then_some((addr, port))
+}
+
+/// Whether a string looks like the address part of an `addr:port` token.
+///
+/// This is deliberately strict to avoid false positives such as timestamps.
+fn is_address(addr: &str) -> bool {
+    static HOSTNAME_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^[A-Za-z][A-Za-z0-9.-]*$").unwrap());
+
+    // strip interface scope, e.g. `127.0.0.53%lo` or `[fe80::1%eth0]`
+    let addr = addr.split('%').next().unwrap_or_default();
+    let addr = addr.trim_start_matches('[').trim_end_matches(']');
+
+    addr == "*"
+        // `netstat` prints `:::22` for IPv6
+        || addr == "::"
+        || addr.parse::<IpAddr>().is_ok()
+        || HOSTNAME_REGEX.is_match(addr)
+}
+
+/// Resolve a service name to a port number.
+///
+/// If the service has multiple ports, prefer the one of the matching protocol.
+fn resolve_service(
+    name: &str,
+    protocol: SupportedProtocol,
+    service_index: &ServiceIndex,
+) -> Option<u16> {
+    let service = service_index.resolve(name)?;
+    let ports = service
+        .ports
+        .iter()
+        .filter_map(|p| match p {
+            ServicePort::Port(port) => Some(*port),
+            ServicePort::Range(_) => None,
+        })
+        .collect_vec();
+    ports
+        .iter()
+        .find(|p| p.protocol == protocol)
+        .or(ports.first())
+        .map(|p| p.number)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::services::parse_services_file;
+
+    const SERVICES: &str = "\
+ssh     22/tcp
+domain  53/tcp
+domain  53/udp
+https   443/tcp
+https   443/udp
+mdns    5353/udp
+split   1000/tcp
+split   1001/udp
+";
+
+    fn service_index() -> ServiceIndex {
+        ServiceIndex::build([&parse_services_file(SERVICES, Path::new("services"))])
+    }
+
+    fn ports(line: &str) -> Vec<String> {
+        find_ports(line, &service_index())
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn finds_ports_in_ss_output() {
+        let line = r#"tcp   LISTEN 0      128        0.0.0.0:22        0.0.0.0:*    users:(("sshd",pid=812,fd=3))"#;
+        assert_eq!(ports(line), ["22/tcp"]);
+        let line = r#"tcp   LISTEN 0      511           [::]:443          [::]:*    users:(("nginx",pid=1234,fd=7))"#;
+        assert_eq!(ports(line), ["443/tcp"]);
+        let line = r#"udp   UNCONN 0      0    127.0.0.53%lo:53        0.0.0.0:*    users:(("systemd-resolve",pid=645,fd=13))"#;
+        assert_eq!(ports(line), ["53/udp"]);
+    }
+
+    #[test]
+    fn finds_ports_in_netstat_output() {
+        let line = "tcp        0      0 127.0.0.1:5432          0.0.0.0:*               LISTEN      1001/postgres";
+        assert_eq!(ports(line), ["5432/tcp"]);
+        let line = "udp6       0      0 :::5353                 :::*                                1034/avahi-daemon";
+        assert_eq!(ports(line), ["5353/udp"]);
+        // BSD `netstat -an`
+        let line = "tcp4       0      0  127.0.0.1.631          *.*                    LISTEN";
+        assert_eq!(ports(line), ["631/tcp"]);
+    }
+
+    #[test]
+    fn finds_ports_in_lsof_output() {
+        let line = "sshd       812   root    3u  IPv4  21345      0t0  TCP *:ssh (LISTEN)";
+        assert_eq!(ports(line), ["22/tcp"]);
+        let line = "firefox   4242   user   87u  IPv4 998877      0t0  TCP 192.168.1.5:51234->142.250.1.1:https (ESTABLISHED)";
+        assert_eq!(ports(line), ["51234/tcp", "443/tcp"]);
+        let line = "avahi-dae 1034  avahi   12u  IPv6  30123      0t0  UDP [::]:mdns";
+        assert_eq!(ports(line), ["5353/udp"]);
+    }
+
+    #[test]
+    fn finds_ports_in_nmap_output() {
+        let line =
+            "22/tcp   open  ssh     OpenSSH 8.9p1 Ubuntu 3ubuntu0.1 (Ubuntu Linux; protocol 2.0)";
+        assert_eq!(ports(line), ["22/tcp"]);
+        let line = "53/udp   open|filtered domain";
+        assert_eq!(ports(line), ["53/udp"]);
+    }
+
+    #[test]
+    fn ignores_numbers_that_are_not_ports() {
+        assert!(ports("RX packets 123456  bytes 98765432 (94.1 MiB)").is_empty());
+        assert!(ports("2024-05-01 12:34:56 sshd[812]: Accepted publickey").is_empty());
+        assert!(ports("Nmap done: 1 IP address (1 host up) scanned in 0.52 seconds").is_empty());
+        assert!(ports("tcp   LISTEN 0  128  0.0.0.0:*  0.0.0.0:*").is_empty());
+    }
+
+    #[test]
+    fn detects_protocol() {
+        use SupportedProtocol as P;
+        assert_eq!(detect_protocol("tcp6  0  0 :::22  :::*  LISTEN"), P::Tcp);
+        assert_eq!(detect_protocol("UDP   UNCONN 0 0  *:68  *:*"), P::Udp);
+        assert_eq!(
+            detect_protocol("sshd 812 root 3u IPv4 21345 0t0 TCP *:ssh (LISTEN)"),
+            P::Tcp
+        );
+        assert_eq!(detect_protocol("LISTEN 0 128 0.0.0.0:22 0.0.0.0:*"), P::Any);
+        assert_eq!(detect_protocol(""), P::Any);
+    }
+
+    #[test]
+    fn splits_addresses() {
+        assert_eq!(split_address("0.0.0.0:22"), Some(("0.0.0.0", "22")));
+        assert_eq!(split_address("[::]:443"), Some(("[::]", "443")));
+        assert_eq!(split_address(":::22"), Some(("::", "22")));
+        assert_eq!(
+            split_address("(127.0.0.1:8080),"),
+            Some(("127.0.0.1", "8080"))
+        );
+        assert_eq!(split_address("127.0.0.1.631"), Some(("127.0.0.1", "631")));
+        assert_eq!(split_address("*.22"), Some(("*", "22")));
+        assert_eq!(split_address("94.1"), None);
+        assert_eq!(split_address("98765432"), None);
+    }
+
+    #[test]
+    fn recognises_addresses() {
+        for addr in [
+            "*",
+            "::",
+            "[::]",
+            "0.0.0.0",
+            "[fe80::1%eth0]",
+            "127.0.0.53%lo",
+            "localhost",
+        ] {
+            assert!(is_address(addr), "{addr}");
+        }
+        for addr in ["", "12:34", "2024-05-01 12", "sshd[812]", "0t0"] {
+            assert!(!is_address(addr), "{addr}");
+        }
+    }
+
+    #[test]
+    fn resolves_services() {
+        use SupportedProtocol as P;
+        let index = service_index();
+        assert_eq!(resolve_service("ssh", P::Tcp, &index), Some(22));
+        assert_eq!(resolve_service("SSH", P::Any, &index), Some(22));
+        assert_eq!(resolve_service("split", P::Udp, &index), Some(1001));
+        assert_eq!(resolve_service("split", P::Any, &index), Some(1000));
+        assert_eq!(resolve_service("nonexistent", P::Tcp, &index), None);
+    }
+}
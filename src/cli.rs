@@ -1,130 +1,495 @@
-use std::{convert::Infallible, fmt, str::FromStr};
+//! Command line arguments.
 
-use clap::Parser;
+#[cfg(feature = "server")]
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
 use clap_verbosity_flag::{Verbosity, WarnLevel};
-use serde_with::SerializeDisplay;
 
+use crate::{
+    consts::DEFAULT_SERVICES_PATH,
+    query::{DataSourceKind, PortRangeSelection, PortSelection, SupportedProtocol, UserQueryList},
+    store::{PortCategory, PortType},
+};
+
+/// The command line arguments of `wtp`.
 #[derive(Clone, Debug, Parser)]
-#[command(author, version)]
+#[command(author, version, subcommand_negates_reqs = true)]
 pub struct CliArgs {
-    /// A port specification or a plain text search term.
+    /// The subcommand to run instead of looking up queries.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// One or more port specifications or plain text search terms.
     ///
     /// ## Port specification
     /// - either a port number: `80`
     /// - or a number-protocol pair: `443/udp`
-    #[arg(index = 1, value_name = "QUERY")]
-    pub query: UserQuery,
+    /// - or a port range, optionally with a protocol: `8000-8100/tcp`
+    /// - or a comma-separated list of the above: `22,80,443/udp`
+    ///
+    /// ## Search term
+    /// - if the term is the exact name of a known service (e.g. `ssh`),
+    ///   the ports of the service are looked up
+    /// - otherwise, port descriptions are searched for the term
+    /// - to search for a subcommand name (e.g. `free` or `cache`),
+    ///   put `--` before it: `wtp -- cache`
+    ///
+    /// ## Search syntax
+    /// - a regex between slashes: `/postgres(ql)?/`
+    /// - otherwise, every term must be present: `secure shell`
+    /// - terms combined with `AND` (the default) and `OR`: `kafka OR zookeeper`;
+    ///   `AND` binds tighter than `OR`
+    /// - a term prefixed with `-` must be absent: `kafka -zookeeper`
+    /// - a quoted phrase matches as a whole: `"secure shell" OR ssh`
+    /// - quote the entire search so that it is a single argument:
+    ///   `wtp 'kafka OR zookeeper'`
+    /// - all matching is case-insensitive
+    #[arg(index = 1, value_name = "QUERY", num_args = 1..)]
+    #[cfg_attr(feature = "repl", arg(required_unless_present = "interactive"))]
+    #[cfg_attr(not(feature = "repl"), arg(required = true))]
+    pub queries: Vec<UserQueryList>,
+
+    /// Start an interactive prompt instead of answering queries from the command line.
+    ///
+    /// The data sources are only loaded once. Type `:help` in the prompt for commands.
+    #[cfg(feature = "repl")]
+    #[arg(short = 'i', long = "interactive", conflicts_with = "queries")]
+    pub interactive: bool,
+
+    /// Which data sources to look up from.
+    ///
+    /// When multiple sources are specified, results are shown for each of them.
+    ///
+    /// If the Wikipedia page has never been pulled, the local services file
+    /// is used instead.
+    #[arg(
+        short = 's',
+        long = "source",
+        value_name = "SOURCE",
+        value_delimiter = ',',
+        default_value = "wikipedia",
+        global = true
+    )]
+    pub sources: Vec<DataSourceKind>,
+
+    /// Read the IANA registry from this CSV file instead of the local cache.
+    #[arg(long = "iana-file", value_name = "PATH", global = true)]
+    pub iana_file: Option<PathBuf>,
+
+    /// Read services from this file when using the `services` source.
+    #[arg(long = "services-file", value_name = "PATH", default_value = DEFAULT_SERVICES_PATH, global = true)]
+    pub services_file: PathBuf,
+
+    /// Only show use cases with any of these port types.
+    ///
+    /// Possible values: yes, unofficial, assigned, no, reserved.
+    /// E.g. `--type yes,assigned` to only show uses assigned by IANA.
+    #[arg(
+        long = "type",
+        value_name = "TYPE",
+        value_delimiter = ',',
+        global = true
+    )]
+    pub port_types: Vec<PortType>,
+
+    /// Only show use cases for this protocol.
+    ///
+    /// This applies to searches as well as lookups.
+    #[arg(
+        long = "proto",
+        value_name = "PROTOCOL",
+        default_value = "any",
+        global = true
+    )]
+    pub protocol: SupportedProtocol,
+
+    /// Only show use cases with ports in any of these categories.
+    ///
+    /// Possible values: well-known, registered, dynamic.
+    #[arg(
+        long = "category",
+        value_name = "CATEGORY",
+        value_delimiter = ',',
+        global = true
+    )]
+    pub categories: Vec<PortCategory>,
+
+    /// Use this URL for the Wikipedia history API of the page, e.g. to use a mirror.
+    #[arg(
+        long = "history-api-url",
+        value_name = "URL",
+        env = "WTP_HISTORY_API_URL",
+        global = true,
+        help_heading = "Source URLs"
+    )]
+    pub history_api_url: Option<String>,
+
+    /// Use this URL for the Wikipedia page, e.g. to use a mirror.
+    ///
+    /// Links to notes and references are also based on this URL.
+    #[arg(
+        long = "page-url",
+        value_name = "URL",
+        env = "WTP_PAGE_URL",
+        global = true,
+        help_heading = "Source URLs"
+    )]
+    pub page_url: Option<String>,
+
+    /// Use this base URL for links to other Wikipedia pages.
+    #[arg(
+        long = "origin-base-url",
+        value_name = "URL",
+        env = "WTP_ORIGIN_BASE_URL",
+        global = true,
+        help_heading = "Source URLs"
+    )]
+    pub origin_base_url: Option<String>,
+
+    /// Use this URL for the IANA registry in CSV form, e.g. to use a mirror.
+    #[arg(
+        long = "iana-url",
+        value_name = "URL",
+        env = "WTP_IANA_URL",
+        global = true,
+        help_heading = "Source URLs"
+    )]
+    pub iana_registry_url: Option<String>,
 
     /// Which Wikipedia page revision you would like to use.
     ///
     /// If unspecified, use the latest revision from either online or local cache,
     /// depending on whether `--pull` is used.
-    #[arg(long = "revision", visible_alias = "rev")]
+    #[arg(long = "revision", visible_alias = "rev", global = true)]
     pub revision: Option<u64>,
 
     /// Attempt to retrieve revisions from Wikipedia.
     ///
     /// If `--revision` is unspecified, this will pull the latest revision.
-    #[arg(short = 'p', long = "pull", visible_alias = "online")]
+    #[arg(
+        short = 'p',
+        long = "pull",
+        visible_alias = "online",
+        overrides_with = "no_pull",
+        global = true
+    )]
     pub pull: bool,
 
+    /// Only use the local cache, even if the config enables `pull`.
+    #[arg(
+        long = "no-pull",
+        visible_alias = "offline",
+        overrides_with = "pull",
+        global = true
+    )]
+    pub no_pull: bool,
+
     /// Show an additional link section.
     ///
     /// Note: when outputting to TTY, inline hyperlinks are always available
     /// regardless of this option. This option is most useful when redirecting
     /// output to a file, or when your terminal does not support OSC8.
-    #[arg(short = 'l', long = "links", alias = "link")]
+    #[arg(
+        short = 'l',
+        long = "links",
+        alias = "link",
+        overrides_with = "no_links",
+        global = true
+    )]
     pub show_links: bool,
 
+    /// Do not show the link section, even if the config enables `links`.
+    #[arg(long = "no-links", overrides_with = "show_links", global = true)]
+    pub no_links: bool,
+
     /// Show notes and references in the port description.
     ///
     /// Note: in contrast to links, notes and references will not be shown inline
     /// without this option.
-    #[arg(short = 'r', long = "references", visible_aliases = ["refs", "notes"], aliases = ["reference", "ref", "note"])]
+    #[arg(short = 'r', long = "references", visible_aliases = ["refs", "notes"], aliases = ["reference", "ref", "note"], overrides_with = "no_notes_and_references", global = true)]
     pub show_notes_and_references: bool,
 
+    /// Do not show notes and references, even if the config enables `references`.
+    #[arg(long = "no-references", visible_aliases = ["no-refs", "no-notes"], overrides_with = "show_notes_and_references", global = true)]
+    pub no_notes_and_references: bool,
+
     /// Use machine-friendly JSON output.
-    #[arg(short = 'j', long = "json")]
+    ///
+    /// When there are multiple queries, one JSON object is printed per line.
+    #[arg(
+        short = 'j',
+        long = "json",
+        overrides_with = "text_output",
+        global = true
+    )]
     pub json_output: bool,
 
+    /// Use human-readable text output, even if the config sets `output = "json"`.
+    #[arg(long = "text", overrides_with = "json_output", global = true)]
+    pub text_output: bool,
+
+    /// Consider the cached revision stale this long after it was last
+    /// confirmed to be the latest revision, e.g. `30days` or `12h`.
+    ///
+    /// A warning is shown when using a stale revision. If the config sets
+    /// `pull = "if-stale"`, the latest revision is retrieved in the background
+    /// for next time. Defaults to 30 days.
+    #[arg(long = "stale-after", value_name = "DURATION", global = true)]
+    pub stale_after: Option<humantime::Duration>,
+
+    /// Give up on each network request attempt after this long, e.g. `30s`.
+    ///
+    /// Defaults to 10 seconds.
+    #[arg(
+        long = "timeout",
+        value_name = "DURATION",
+        env = "WTP_TIMEOUT",
+        global = true,
+        help_heading = "Network"
+    )]
+    pub timeout: Option<humantime::Duration>,
+
+    /// Retry failed network requests this many times.
+    ///
+    /// Only connection errors, timeouts and server errors are retried.
+    /// Defaults to 2.
+    #[arg(
+        long = "retries",
+        value_name = "N",
+        env = "WTP_RETRIES",
+        global = true,
+        help_heading = "Network"
+    )]
+    pub retries: Option<u32>,
+
+    /// Wait this long before the first retry, doubling for each following retry.
+    ///
+    /// Defaults to 1 second.
+    #[arg(
+        long = "retry-delay",
+        value_name = "DURATION",
+        env = "WTP_RETRY_DELAY",
+        global = true,
+        help_heading = "Network"
+    )]
+    pub retry_delay: Option<humantime::Duration>,
+
+    /// Send all network requests through this proxy, e.g. `http://proxy:3128`.
+    ///
+    /// By default, `HTTPS_PROXY`, `HTTP_PROXY`, `ALL_PROXY` and `NO_PROXY`
+    /// are respected. `NO_PROXY` still applies when this is set.
+    #[arg(
+        long = "proxy",
+        value_name = "URL",
+        env = "WTP_PROXY",
+        global = true,
+        help_heading = "Network"
+    )]
+    pub proxy: Option<String>,
+
+    /// Trust the CA certificates in this PEM file in addition to the built-in ones.
+    #[arg(
+        long = "ca-bundle",
+        value_name = "PATH",
+        env = "WTP_CA_BUNDLE",
+        global = true,
+        help_heading = "Network"
+    )]
+    pub ca_bundle: Option<PathBuf>,
+
+    /// Read the config file from this path.
+    ///
+    /// By default, `config.toml` in the user config directory is read if it exists.
+    #[arg(
+        long = "config",
+        value_name = "PATH",
+        env = "WTP_CONFIG",
+        global = true
+    )]
+    pub config: Option<PathBuf>,
+
+    /// Cache retrieved pages in this directory instead of the user cache directory.
+    #[arg(long = "cache-dir", value_name = "PATH", global = true)]
+    pub cache_dir: Option<PathBuf>,
+
+    /// How verbose the logs are.
     #[command(flatten)]
     pub verbosity: Verbosity<WarnLevel>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub enum UserQuery {
-    /// User specified a port lookup.
-    PortLookup(PortSelection),
-    /// User specified a search term.
-    Search(String),
-}
-impl fmt::Display for UserQuery {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::PortLookup(port) => write!(f, "{port}"),
-            Self::Search(s) => write!(f, r#"Search: "{s}""#),
-        }
-    }
-}
-impl FromStr for UserQuery {
-    type Err = Infallible;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let query = if let Ok(port) = s.parse() {
-            Self::PortLookup(port)
-        } else {
-            Self::Search(s.into())
-        };
-        Ok(query)
-    }
-}
+/// The subcommands of `wtp`.
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    /// Suggest ports or blocks of ports that have no known use cases.
+    ///
+    /// Use `--proto` to only consider use cases of a specific protocol,
+    /// and `--category` to only suggest ports in specific categories.
+    Free {
+        /// The range of ports to search in.
+        ///
+        /// A protocol can be specified like in queries, e.g. `20000-30000/tcp`.
+        #[arg(long = "range", value_name = "RANGE", default_value = "1024-49151")]
+        range: PortRangeSelection,
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, SerializeDisplay)]
-pub struct PortSelection {
-    pub number: u16,
-    pub protocol: SupportedProtocol,
-}
-impl fmt::Display for PortSelection {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let PortSelection { number, protocol } = self;
-        match protocol {
-            SupportedProtocol::Any => write!(f, "{number}"),
-            proto => write!(f, "{number}/{proto}"),
-        }
-    }
+        /// The number of ports or blocks of ports to suggest.
+        #[arg(short = 'n', long = "count", default_value_t = 5)]
+        count: usize,
+
+        /// Suggest contiguous blocks of this many ports.
+        #[arg(short = 'b', long = "block", value_name = "SIZE", default_value_t = 1,
+            value_parser = clap::value_parser!(u16).range(1..))]
+        block_size: u16,
+
+        /// Consider ports whose only known use cases are unofficial as free.
+        #[arg(long = "ignore-unofficial")]
+        ignore_unofficial: bool,
+
+        /// Do not suggest blocks that cross a port category boundary.
+        #[arg(long = "within-category")]
+        within_category: bool,
+    },
+
+    /// Show what is listening on the local machine and look up each port.
+    ///
+    /// This reads `/proc/net/{tcp,udp,tcp6,udp6}`. The owning process of each
+    /// socket is shown where permitted.
+    Local {
+        /// Where the proc filesystem is mounted.
+        #[arg(long = "proc-root", value_name = "PATH", default_value = "/proc")]
+        proc_root: PathBuf,
+    },
+
+    /// Look up the open ports in an nmap XML report, as produced by `nmap -oX`.
+    Nmap {
+        /// The report to read. Use `-` to read from stdin.
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+
+    /// Show the use cases that changed between two Wikipedia page revisions.
+    ///
+    /// The revisions are retrieved from the local cache, or from Wikipedia
+    /// if `--pull` is used.
+    Diff {
+        /// The older revision.
+        #[arg(value_name = "OLD_REVISION")]
+        old: u64,
+
+        /// The newer revision.
+        #[arg(value_name = "NEW_REVISION")]
+        new: u64,
+    },
+
+    /// Show how the use cases of a port changed across all cached Wikipedia
+    /// page revisions.
+    ///
+    /// If `--pull` is used, the latest revision is retrieved as well.
+    History {
+        /// The port to trace, optionally with a protocol, e.g. `9090/tcp`.
+        #[arg(value_name = "PORT")]
+        port: PortSelection,
+
+        /// Only trace the N most recent revisions.
+        ///
+        /// If `--pull` is used, those that are not cached are retrieved, so
+        /// keep this small to avoid downloading many revisions.
+        #[arg(long = "last", value_name = "N",
+            value_parser = clap::value_parser!(u32).range(1..))]
+        last: Option<u32>,
+    },
+
+    /// Manage the locally cached Wikipedia page revisions.
+    Cache {
+        /// What to do with the cache.
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+
+    /// Browse the port table in a full-screen terminal UI.
+    ///
+    /// Press `/` to search incrementally, and `Tab` to switch between sources.
+    #[cfg(feature = "tui")]
+    Browse {
+        /// Only list use cases in this range, e.g. `5000-6000`.
+        ///
+        /// A protocol can be specified like in queries, e.g. `5000-6000/tcp`.
+        range: Option<PortRangeSelection>,
+    },
+
+    /// Serve lookups over a local HTTP API, responding in JSON.
+    ///
+    /// Endpoints: `/lookup/{port}[/{proto}]`, `/search?q=...` and `/revisions`.
+    /// Responses have the same shape as JSON output on the command line,
+    /// with one result per data source.
+    #[cfg(feature = "server")]
+    Serve {
+        /// The address to listen on.
+        #[arg(long = "listen", value_name = "ADDR", default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+
+        /// Check the cache for a newer revision of the Wikipedia page this often,
+        /// e.g. after `wtp --pull` is run elsewhere.
+        ///
+        /// Has no effect if `--revision` is specified.
+        #[arg(
+            long = "reload-interval",
+            value_name = "DURATION",
+            default_value = "1min"
+        )]
+        reload_interval: humantime::Duration,
+    },
+
+    /// Annotate ports in the output of `ss`, `netstat`, `lsof -i` or `nmap`
+    /// piped to stdin.
+    ///
+    /// Each line is printed as it is read, followed by the best known use case
+    /// of each port found in it.
+    #[command(visible_alias = "filter")]
+    Annotate,
 }
-impl FromStr for PortSelection {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (number_str, protocol) = match s.split_once('/') {
-            Some((n, p)) => {
-                let proto = p
-                    .parse()
-                    .map_err(|_| format!(r#"Unknown protocol: "{p}""#))?;
-                (n, proto)
-            }
-            None => (s, SupportedProtocol::Any),
-        };
-        let number = number_str
-            .parse()
-            .map_err(|_| format!(r#""{number_str}" is not a valid port number"#))?;
-        Ok(Self { number, protocol })
-    }
+
+/// The actions of the `cache` subcommand.
+#[derive(Clone, Debug, Subcommand)]
+pub enum CacheAction {
+    /// List all cached revisions with their sizes and modification times.
+    #[command(visible_alias = "ls")]
+    List,
+
+    /// Remove all but the newest cached revisions.
+    Prune {
+        /// The number of newest revisions to keep.
+        #[arg(long = "keep", value_name = "N", default_value_t = 1)]
+        keep: usize,
+    },
+
+    /// Remove specific cached revisions.
+    #[command(visible_alias = "remove")]
+    Rm {
+        /// The revisions to remove.
+        #[arg(value_name = "REVISION", required = true, num_args = 1..)]
+        revisions: Vec<u64>,
+    },
+
+    /// Parse cached revisions again and report any errors.
+    ///
+    /// This also replaces the cached parse results.
+    Verify {
+        /// The revisions to verify. If unspecified, verify all cached revisions.
+        #[arg(value_name = "REVISION")]
+        revisions: Vec<u64>,
+    },
 }
 
-/// Known port protocols.
-#[derive(Copy, Clone, Debug, PartialEq, Eq, strum::Display, strum::EnumString)]
-#[strum(serialize_all = "lowercase")]
-pub enum SupportedProtocol {
-    /// Protocol unspecified.
-    Any,
-    /// Transmission Control Protocol.
-    Tcp,
-    /// User Datagram Protocol.
-    Udp,
-    /// Stream Control Transmission Protocol.
-    Sctp,
-    /// Datagram Congestion Control Protocol.
-    Dccp,
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn cli_is_valid() {
+        CliArgs::command().debug_assert();
+    }
 }
@@ -0,0 +1,151 @@
+//! The user configuration file.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Context, OptionExt};
+use directories::ProjectDirs;
+use log::debug;
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
+use tokio::fs;
+
+/// User configuration, loaded from a TOML file.
+///
+/// All options are optional. Command line arguments always take precedence.
+#[serde_as]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// Show an additional link section by default.
+    pub links: Option<bool>,
+    /// Show notes and references by default.
+    pub references: Option<bool>,
+    /// The default output format.
+    pub output: Option<OutputFormat>,
+    /// Whether to retrieve revisions from Wikipedia by default.
+    pub pull: Option<PullPolicy>,
+    /// How long after being confirmed to be the latest revision the cached
+    /// revision is considered stale, e.g. `30days`.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub stale_after: Option<humantime::Duration>,
+    /// Where to cache retrieved pages instead of the default location.
+    pub cache_dir: Option<PathBuf>,
+    /// Overrides of the data source URLs, e.g. to use a mirror.
+    pub urls: UrlConfig,
+    /// Network options.
+    pub http: HttpConfig,
+}
+impl Config {
+    /// The default location of the configuration file, e.g.
+    /// `~/.config/what-the-port/config.toml` on Linux.
+    ///
+    /// Errors if the home directory cannot be determined.
+    pub fn default_path() -> color_eyre::Result<PathBuf> {
+        Ok(project_dirs()?.config_dir().join("config.toml"))
+    }
+
+    /// Load the configuration file.
+    ///
+    /// If the file is at the default location and does not exist, the default
+    /// configuration is used. Otherwise a missing file is an error.
+    pub async fn load(path: impl AsRef<Path>, is_default_path: bool) -> color_eyre::Result<Self> {
+        let path = path.as_ref();
+
+        if is_default_path && !path.exists() {
+            debug!("No config file at {path:?}, using defaults");
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)
+            .await
+            .wrap_err_with(|| format!("Failed to read config file at {path:?}"))?;
+        let config = toml::from_str(&content)
+            .wrap_err_with(|| format!("Failed to parse config file at {path:?}"))?;
+        debug!("Loaded config file from {path:?}");
+        Ok(config)
+    }
+}
+
+/// The default cache directory, e.g. `~/.cache/what-the-port` on Linux.
+///
+/// Errors if the home directory cannot be determined.
+pub fn default_cache_dir() -> color_eyre::Result<PathBuf> {
+    Ok(project_dirs()?.cache_dir().to_owned())
+}
+
+/// The directories of this program according to platform conventions.
+fn project_dirs() -> color_eyre::Result<ProjectDirs> {
+    ProjectDirs::from("org", "wtp", "what-the-port")
+        .ok_or_eyre("Cannot determine your home directory")
+}
+
+/// Overrides of the data source URLs.
+///
+/// See `--help` for the meaning of each URL.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct UrlConfig {
+    /// The MediaWiki API endpoint listing the revisions of the page.
+    pub history_api: Option<String>,
+    /// The URL of the page, used to retrieve its revisions.
+    pub page: Option<String>,
+    /// The base URL of links to other Wikipedia pages.
+    pub origin_base: Option<String>,
+    /// The URL of the IANA registry in CSV format.
+    pub iana_registry: Option<String>,
+}
+
+/// Network options.
+///
+/// See `--help` for the meaning of each option.
+#[serde_as]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct HttpConfig {
+    /// The timeout of each request.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub timeout: Option<humantime::Duration>,
+    /// How many times a failed request is retried.
+    pub retries: Option<u32>,
+    /// The delay before the first retry.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub retry_delay: Option<humantime::Duration>,
+    /// The proxy to send requests through.
+    pub proxy: Option<String>,
+    /// Additional CA certificates to trust, in PEM format.
+    pub ca_bundle: Option<PathBuf>,
+}
+
+/// Output formats.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Human-readable text.
+    Text,
+    /// Machine-friendly JSON.
+    Json,
+}
+
+/// When to retrieve revisions from Wikipedia.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PullPolicy {
+    /// Only use the local cache, unless `--pull` is used.
+    Never,
+    /// Always retrieve the latest revision, unless `--no-pull` is used.
+    Always,
+    /// Use the local cache, but retrieve the latest revision in the background
+    /// if the cached revision is stale, unless `--no-pull` is used.
+    IfStale,
+}
+
+/// Resolve a boolean option from a pair of negating flags and the config.
+///
+/// Because the flags override each other, at most one of them is set.
+pub fn resolve_flag(enabled: bool, disabled: bool, config: Option<bool>) -> bool {
+    match (enabled, disabled) {
+        (true, _) => true,
+        (_, true) => false,
+        _ => config.unwrap_or(false),
+    }
+}
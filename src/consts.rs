@@ -1,4 +1,6 @@
-use std::env;
+//! Default URLs, paths and other constants.
+
+use std::{env, time::Duration};
 
 use const_format::formatcp;
 
@@ -19,3 +21,20 @@ pub const PAGE_URL: &str = "https://en.wikipedia.org/wiki/List_of_TCP_and_UDP_po
 
 /// The base URL for same-site links.
 pub const ORIGIN_BASE_URL: &str = "https://en.wikipedia.org";
+
+/// The URL for the IANA Service Name and Transport Protocol Port Number Registry in CSV form.
+pub const IANA_REGISTRY_URL: &str =
+    "https://www.iana.org/assignments/service-names-port-numbers/service-names-port-numbers.csv";
+
+/// The default location of the local services file.
+pub const DEFAULT_SERVICES_PATH: &str = "/etc/services";
+
+/// The version of the parser, used to invalidate cached parse results.
+///
+/// Any release may change how the page is parsed, so we simply use the
+/// package version.
+pub const PARSER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// How long after being confirmed to be the latest revision the cached revision
+/// is considered stale by default.
+pub const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(30 * 24 * 60 * 60);
@@ -1,13 +1,17 @@
-use std::{fmt, ops::RangeInclusive};
+//! Structured output of queries, rendered as text or JSON.
+
+use std::{fmt, net::IpAddr, ops::RangeInclusive, time::SystemTime};
 
 use itertools::Itertools;
 use serde::Serialize;
+use serde_with::{serde_as, DisplayFromStr, TimestampSeconds};
 
 use crate::{
-    cli::PortSelection,
-    consts::{ORIGIN_BASE_URL, PAGE_URL},
+    iana::IanaRegistration,
     parse::RichTextSpan,
-    store::{PortCategory, PortRangeInfo, PortType},
+    query::{PortRangeSelection, PortSelection, SupportedProtocol},
+    source::{CachedRevision, SourceUrls},
+    store::{DataSource, PortCategory, PortRangeInfo, PortType},
 };
 
 /// Short-hand macro to colorize output.
@@ -18,28 +22,48 @@ macro_rules! color {
     }};
 }
 
-/// Short-hand macro to stylise linked text.
-macro_rules! hyperlink {
-    ($item: expr, $fg: ident, $url: expr) => {{
-        let style = yansi::Style::new().fg(yansi::Color::$fg).italic();
-        yansi::hyperlink::HyperlinkExt::link(&yansi::Paint::paint(&$item, style), $url)
-    }};
-}
-
 /// All possible kinds of output, serialisable into either human-readable or
 /// machine-readable form.
 #[derive(Clone, Debug, derive_more::Display, derive_more::From, Serialize)]
 #[serde(tag = "type", content = "result", rename_all = "kebab-case")]
 pub enum Output<'a> {
+    /// The result of a port lookup.
     PortLookup(PortLookupOutput<'a>),
+    /// The result of a port range lookup.
+    RangeLookup(RangeLookupOutput<'a>),
+    /// The result of a service name lookup.
+    ServiceLookup(ServiceLookupOutput<'a>),
+    /// The result of a general search.
     Search(SearchOutput<'a>),
+    /// The result of a free port search.
+    Free(FreePortsOutput<'a>),
+    /// The listening sockets on the local machine.
+    Local(LocalOutput<'a>),
+    /// The open ports in an nmap report.
+    Nmap(NmapOutput<'a>),
+    /// The changes between two databases.
+    Diff(DiffOutput<'a>),
+    /// The history of a port across revisions.
+    History(HistoryOutput<'a>),
+    /// The revisions in the local cache.
+    CacheList(CacheListOutput),
+    /// The revisions removed from the local cache.
+    CacheRemoval(CacheRemovalOutput),
+    /// The results of re-parsing cached revisions.
+    CacheVerify(CacheVerifyOutput),
+    /// A line of input with its ports annotated.
+    Annotation(AnnotatedLineOutput<'a>),
 }
 
 /// Structured output data in response to a port lookup.
 #[derive(Clone, Debug, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct PortLookupOutput<'a> {
+    /// Where the information came from.
+    pub source: &'a DataSource,
+    /// The requested port.
     pub lookup: PortSelection,
+    /// The use cases of the port, if any.
     pub matched: Option<MatchedPort<'a>>,
 }
 impl fmt::Display for PortLookupOutput<'_> {
@@ -49,9 +73,10 @@ impl fmt::Display for PortLookupOutput<'_> {
         let Some(matched) = &self.matched else {
             return write!(
                 f,
-                "Port {p} is a {c} port with no known use cases",
+                "Port {p} is a {c} port with no known use cases according to {s}",
                 p = color!(self.lookup, Red),
                 c = color!(category, Blue),
+                s = color!(self.source, Magenta),
             );
         };
 
@@ -59,9 +84,10 @@ impl fmt::Display for PortLookupOutput<'_> {
         let use_cases_str = matched.format_use_cases(true, Some("    "), "\n");
         write!(
             f,
-            "Port {p} is a {c} port with {count} known use {case_form}\n{use_cases_str}",
+            "Port {p} is a {c} port with {count} known use {case_form} according to {s}\n{use_cases_str}",
             p = color!(self.lookup, Green),
             c = color!(category, Blue),
+            s = color!(self.source, Magenta),
             case_form = if count == 1 { "case" } else { "cases" },
         )?;
 
@@ -88,48 +114,74 @@ impl fmt::Display for PortLookupOutput<'_> {
 #[derive(Clone, Debug, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct SearchOutput<'a> {
+    /// Where the information came from.
+    pub source: &'a DataSource,
+    /// The search query, as entered by the user.
     pub search: String,
+    /// The ports with matching use cases.
     pub matched: Vec<MatchedPort<'a>>,
 }
 impl fmt::Display for SearchOutput<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let Self { search, matched } = self;
+        let Self { source, search, matched } = self;
 
         if matched.is_empty() {
-            return write!(f, "Found no matches for \"{search}\" among known ports");
+            return write!(
+                f,
+                "Found no matches for \"{search}\" among known ports according to {s}",
+                s = color!(source, Magenta),
+            );
         }
 
-        let matched_str = matched
-            .iter()
-            .map(|p| {
-                let category = PortCategory::from(*p.number.start());
-                let case_count = p.use_cases.len();
-
-                let subtitle = if p.number.clone().count() == 1 {
-                    format!(
-                        "Port {p} is a {c} port with {case_count} matched use {case_form}",
-                        p = color!(p.number.start(), Green),
-                        c = color!(category, Blue),
-                        case_form = if case_count == 1 { "case" } else { "cases" },
-                    )
-                } else {
-                    format!(
-                        "Port {p} are {c} ports with {case_count} matched use {case_form}",
-                        p = color!(format!("{}-{}", p.number.start(), p.number.end()), Green),
-                        c = color!(category, Blue),
-                        case_form = if case_count == 1 { "case" } else { "cases" },
-                    )
-                };
-                let use_cases_str = p.format_use_cases(true, Some("    "), "\n");
-                format!("{subtitle}\n{use_cases_str}")
-            })
-            .join("\n\n");
         let port_count = matched.len();
         let case_count = matched.iter().map(|p| p.use_cases.len()).sum::<usize>();
+        write!(
+            f,
+            "Found {port_count} {port_form} with {case_count} use {case_form} matching \"{search}\" according to {s}\n\n",
+            s = color!(source, Magenta),
+            port_form = if port_count == 1 {
+                "port or port range"
+            } else {
+                "ports or port ranges"
+            },
+            case_form = if case_count == 1 { "case" } else { "cases" },
+        )?;
+
+        write_matched_ports(f, matched, "matched")
+    }
+}
+
+/// Structured output data in response to a port range lookup.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RangeLookupOutput<'a> {
+    /// Where the information came from.
+    pub source: &'a DataSource,
+    /// The requested range of ports.
+    pub lookup: PortRangeSelection,
+    /// The ports in the range with known use cases.
+    pub matched: Vec<MatchedPort<'a>>,
+}
+impl fmt::Display for RangeLookupOutput<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { source, lookup, matched } = self;
 
+        if matched.is_empty() {
+            return write!(
+                f,
+                "Port range {r} contains no ports with known use cases according to {s}",
+                r = color!(lookup, Red),
+                s = color!(source, Magenta),
+            );
+        }
+
+        let port_count = matched.len();
+        let case_count = matched.iter().map(|p| p.use_cases.len()).sum::<usize>();
         write!(
             f,
-            "Found {port_count} {port_form} with {case_count} use {case_form} matching \"{search}\"\n\n{matched_str}",
+            "Port range {r} overlaps {port_count} {port_form} with {case_count} known use {case_form} according to {s}\n\n",
+            r = color!(lookup, Green),
+            s = color!(source, Magenta),
             port_form = if port_count == 1 {
                 "port or port range"
             } else {
@@ -138,38 +190,856 @@ impl fmt::Display for SearchOutput<'_> {
             case_form = if case_count == 1 { "case" } else { "cases" },
         )?;
 
-        let links = matched
+        write_matched_ports(f, matched, "known")
+    }
+}
+
+/// Structured output data in response to a search term that matched a
+/// known service name.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ServiceLookupOutput<'a> {
+    /// Where the service name was resolved.
+    pub source: &'a DataSource,
+    /// The service name, as entered by the user.
+    pub service: String,
+    /// The canonical name of the service.
+    pub canonical: String,
+    /// The lookups of each port the service is registered on.
+    pub results: Vec<Output<'a>>,
+}
+impl fmt::Display for ServiceLookupOutput<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { source: _, service, canonical, results } = self;
+
+        let name = if service.trim().eq_ignore_ascii_case(canonical) {
+            format!("{}", color!(canonical, Green))
+        } else {
+            format!("\"{service}\" ({})", color!(canonical, Green))
+        };
+        let results_str = results.iter().join("\n\n");
+        write!(
+            f,
+            "Service {name} is registered on the following ports\n\n{results_str}"
+        )
+    }
+}
+
+/// Structured output data in response to a free port search.
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FreePortsOutput<'a> {
+    /// Where the information came from.
+    pub source: &'a DataSource,
+    /// The range of ports that was searched.
+    pub range: RangeInclusive<u16>,
+    /// The protocol whose use cases were considered.
+    #[serde_as(as = "DisplayFromStr")]
+    pub protocol: SupportedProtocol,
+    /// The number of contiguous free ports requested.
+    pub requested: usize,
+    /// The free ports or blocks of ports found.
+    pub free: Vec<RangeInclusive<u16>>,
+}
+impl fmt::Display for FreePortsOutput<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { source, range, protocol, requested, free } = self;
+
+        let range_str = match protocol {
+            SupportedProtocol::Any => format!("{}-{}", range.start(), range.end()),
+            proto => format!("{}-{}/{proto}", range.start(), range.end()),
+        };
+        let is_block = free.first().is_some_and(|r| r.start() != r.end());
+        let unit = match (is_block, free.len() == 1) {
+            (false, true) => "free port",
+            (false, false) => "free ports",
+            (true, true) => "free block of ports",
+            (true, false) => "free blocks of ports",
+        };
+
+        if free.is_empty() {
+            return write!(
+                f,
+                "Found no free ports in range {r} according to {s}",
+                r = color!(range_str, Red),
+                s = color!(source, Magenta),
+            );
+        }
+
+        let count = free.len();
+        write!(
+            f,
+            "Found {count} {unit} in range {r} according to {s}",
+            r = color!(range_str, Green),
+            s = color!(source, Magenta),
+        )?;
+        if count < *requested {
+            write!(f, " ({requested} requested)")?;
+        }
+
+        let lines = free
             .iter()
-            .flat_map(MatchedPort::format_links)
-            .collect_vec();
-        if !links.is_empty() {
-            let lines = links.iter().map(|line| format!("    {line}")).join("\n");
-            write!(f, "\n\nLinks:\n{lines}")?;
+            .map(|r| {
+                let port_str = if r.start() == r.end() {
+                    r.start().to_string()
+                } else {
+                    format!("{}-{}", r.start(), r.end())
+                };
+                let category = PortCategory::from(*r.start());
+                format!(
+                    "    {} ({})",
+                    color!(port_str, Green),
+                    color!(category, Blue)
+                )
+            })
+            .join("\n");
+        write!(f, "\n{lines}")
+    }
+}
+
+/// Structured output data of all listening sockets on the local machine.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LocalOutput<'a> {
+    /// All listening sockets.
+    pub sockets: Vec<LocalSocketOutput<'a>>,
+}
+impl fmt::Display for LocalOutput<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.sockets.len();
+        if count == 0 {
+            return write!(f, "Found no listening sockets");
         }
 
-        let notes_and_refs = matched
+        let sockets_str = self.sockets.iter().join("\n\n");
+        write!(
+            f,
+            "Found {count} listening {socket_form}\n\n{sockets_str}",
+            socket_form = if count == 1 { "socket" } else { "sockets" },
+        )
+    }
+}
+
+/// A listening socket on the local machine, with its port looked up in each
+/// data source.
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LocalSocketOutput<'a> {
+    /// The transport protocol of the socket.
+    #[serde_as(as = "DisplayFromStr")]
+    pub protocol: SupportedProtocol,
+    /// The local address the socket is bound to.
+    pub address: IpAddr,
+    /// The local port the socket is bound to.
+    pub port: u16,
+    /// The ID of the owning process, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    /// The command name of the owning process, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// The lookups of the port in each data source.
+    pub lookups: Vec<PortLookupOutput<'a>>,
+}
+impl fmt::Display for LocalSocketOutput<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            protocol,
+            address,
+            port,
+            pid,
+            command,
+            lookups,
+        } = self;
+
+        let socket_str = match address {
+            IpAddr::V4(addr) => format!("{addr}:{port}"),
+            IpAddr::V6(addr) => format!("[{addr}]:{port}"),
+        };
+        write!(f, "{protocol} {}", color!(socket_str, Green))?;
+        match (command, pid) {
+            (Some(cmd), Some(pid)) => write!(f, " - {} (pid {pid})", color!(cmd, Yellow))?,
+            (None, Some(pid)) => write!(f, " - pid {pid}")?,
+            _ => {}
+        }
+
+        for lookup in lookups {
+            write!(f, "\n{}", indent_lines(&lookup.to_string()))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Structured output data of the open ports in an nmap report.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NmapOutput<'a> {
+    /// All scanned hosts.
+    pub hosts: Vec<NmapHostOutput<'a>>,
+}
+impl fmt::Display for NmapOutput<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.hosts.len();
+        if count == 0 {
+            return write!(f, "Found no hosts in the nmap report");
+        }
+
+        let hosts_str = self.hosts.iter().join("\n\n");
+        write!(
+            f,
+            "Found {count} {host_form} in the nmap report\n\n{hosts_str}",
+            host_form = if count == 1 { "host" } else { "hosts" },
+        )
+    }
+}
+
+/// A scanned host in an nmap report, with its open ports.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NmapHostOutput<'a> {
+    /// The address of the host.
+    pub address: String,
+    /// The hostname of the host, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// The open ports of the host.
+    pub ports: Vec<NmapPortOutput<'a>>,
+}
+impl fmt::Display for NmapHostOutput<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { address, hostname, ports } = self;
+
+        write!(f, "Host {}", color!(address, Green))?;
+        if let Some(hostname) = hostname {
+            write!(f, " ({})", color!(hostname, Yellow))?;
+        }
+        let count = ports.len();
+        if count == 0 {
+            return write!(f, " has no open ports");
+        }
+        write!(
+            f,
+            " has {count} open {port_form}",
+            port_form = if count == 1 { "port" } else { "ports" },
+        )?;
+
+        for port in ports {
+            write!(f, "\n\n{}", indent_lines(&port.to_string()))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An open port of a scanned host, looked up in each data source.
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NmapPortOutput<'a> {
+    /// The transport protocol of the port.
+    #[serde_as(as = "DisplayFromStr")]
+    pub protocol: SupportedProtocol,
+    /// The port number.
+    pub port: u16,
+    /// The service name guessed by nmap.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// The lookups of the port in each data source.
+    pub lookups: Vec<PortLookupOutput<'a>>,
+}
+impl fmt::Display for NmapPortOutput<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { protocol, port, service, lookups } = self;
+
+        write!(f, "{}", color!(format!("{port}/{protocol}"), Green))?;
+        if let Some(service) = service {
+            write!(f, " - {} according to nmap", color!(service, Yellow))?;
+        }
+
+        for lookup in lookups {
+            write!(f, "\n{}", indent_lines(&lookup.to_string()))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Structured output data of the changes between two databases.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DiffOutput<'a> {
+    /// The database compared against.
+    pub old: &'a DataSource,
+    /// The database compared.
+    pub new: &'a DataSource,
+    /// All changes, ordered by port.
+    pub changes: Vec<PortChange<'a>>,
+}
+impl fmt::Display for DiffOutput<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { old, new, changes } = self;
+
+        let old = color!(old, Magenta);
+        let new = color!(new, Magenta);
+        if changes.is_empty() {
+            return write!(f, "Found no changes from {old} to {new}");
+        }
+
+        let count_of = |pred: fn(&PortChange) -> bool| changes.iter().filter(|c| pred(c)).count();
+        let added = count_of(|c| matches!(c, PortChange::Added { .. }));
+        let removed = count_of(|c| matches!(c, PortChange::Removed { .. }));
+        let modified = count_of(|c| matches!(c, PortChange::Modified { .. }));
+        write!(
+            f,
+            "Found {} added, {} removed and {} modified use cases from {old} to {new}",
+            color!(added, Green),
+            color!(removed, Red),
+            color!(modified, Yellow),
+        )?;
+
+        for change in changes {
+            write!(f, "\n\n{change}")?;
+        }
+
+        write_change_sections(f, changes)
+    }
+}
+
+/// Structured output data of the history of a port across revisions.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HistoryOutput<'a> {
+    /// The requested port.
+    pub lookup: PortSelection,
+    /// The number of revisions searched.
+    pub revision_count: usize,
+    /// The revisions that changed the use cases of the port, oldest first.
+    pub timeline: Vec<HistoryEntry<'a>>,
+}
+impl fmt::Display for HistoryOutput<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { lookup, revision_count, timeline } = self;
+
+        let revision_form = if *revision_count == 1 { "revision" } else { "revisions" };
+        if timeline.is_empty() {
+            return write!(
+                f,
+                "Port {} has no known use cases in {revision_count} cached {revision_form}",
+                color!(lookup, Red),
+            );
+        }
+
+        write!(
+            f,
+            "History of port {} across {revision_count} cached {revision_form}",
+            color!(lookup, Green),
+        )?;
+        for entry in timeline {
+            write!(f, "\n\n{entry}")?;
+        }
+
+        let changes = timeline.iter().flat_map(|e| &e.changes).collect_vec();
+        write_change_sections(f, changes)
+    }
+}
+
+/// The changes to the use cases of a port in a single revision.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HistoryEntry<'a> {
+    /// The revision that made the changes.
+    pub source: &'a DataSource,
+    /// The changes made by this revision.
+    pub changes: Vec<PortChange<'a>>,
+}
+impl fmt::Display for HistoryEntry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let changes_str = self
+            .changes
             .iter()
-            .flat_map(MatchedPort::format_notes_and_refs)
-            .collect_vec();
-        if !notes_and_refs.is_empty() {
-            let lines = notes_and_refs
-                .iter()
-                .map(|line| format!("    {line}"))
-                .join("\n");
-            write!(f, "\n\nNotes and References:\n{lines}")?;
+            .map(|change| indent_lines(&change.to_string()))
+            .join("\n");
+        write!(f, "{}\n{changes_str}", color!(self.source, Magenta))
+    }
+}
+
+/// A change to a single use case between two databases.
+#[derive(Clone, Debug, Serialize)]
+#[serde(
+    tag = "change",
+    rename_all = "kebab-case",
+    rename_all_fields = "kebab-case"
+)]
+pub enum PortChange<'a> {
+    /// A use case that is only in the new database.
+    Added {
+        /// The port or range of ports.
+        number: RangeInclusive<u16>,
+        /// The added use case.
+        use_case: PortUseCase<'a>,
+    },
+    /// A use case that is only in the old database.
+    Removed {
+        /// The port or range of ports.
+        number: RangeInclusive<u16>,
+        /// The removed use case.
+        use_case: PortUseCase<'a>,
+    },
+    /// A use case that is in both databases, but differs.
+    Modified {
+        /// The port or range of ports.
+        number: RangeInclusive<u16>,
+        /// The use case in the old database.
+        old: PortUseCase<'a>,
+        /// The use case in the new database.
+        new: PortUseCase<'a>,
+        /// Whether the description differs.
+        description_changed: bool,
+        /// Whether the protocol types differ.
+        types_changed: bool,
+    },
+}
+impl PortChange<'_> {
+    /// The use cases involved in this change.
+    fn use_cases(&self) -> Vec<&PortUseCase<'_>> {
+        match self {
+            Self::Added { use_case, .. } | Self::Removed { use_case, .. } => vec![use_case],
+            Self::Modified { old, new, .. } => vec![old, new],
+        }
+    }
+}
+impl fmt::Display for PortChange<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format_number = |n: &RangeInclusive<u16>| {
+            if n.start() == n.end() {
+                format!("Port {}", n.start())
+            } else {
+                format!("Ports {}-{}", n.start(), n.end())
+            }
+        };
+
+        match self {
+            Self::Added { number, use_case } => write!(
+                f,
+                "{} {}\n    {}\n        {}",
+                color!("+", Green),
+                color!(format_number(number), Green),
+                use_case.format_description(),
+                use_case.format_protocols(),
+            ),
+            Self::Removed { number, use_case } => write!(
+                f,
+                "{} {}\n    {}\n        {}",
+                color!("-", Red),
+                color!(format_number(number), Red),
+                use_case.format_description(),
+                use_case.format_protocols(),
+            ),
+            Self::Modified {
+                number,
+                old,
+                new,
+                description_changed,
+                types_changed: _,
+            } => {
+                write!(
+                    f,
+                    "{} {}",
+                    color!("~", Yellow),
+                    color!(format_number(number), Yellow)
+                )?;
+                if *description_changed {
+                    write!(
+                        f,
+                        "\n    {} {}\n    {} {}",
+                        color!("-", Red),
+                        old.format_description(),
+                        color!("+", Green),
+                        new.format_description(),
+                    )?;
+                } else {
+                    write!(f, "\n    {}", new.format_description())?;
+                }
+                write!(f, "\n        {}", new.format_protocol_changes(old))
+            }
+        }
+    }
+}
+
+/// Structured output data of the revisions in the local cache.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CacheListOutput {
+    /// All cached revisions, oldest first.
+    pub revisions: Vec<CachedRevisionOutput>,
+}
+impl From<Vec<CachedRevision>> for CacheListOutput {
+    fn from(revisions: Vec<CachedRevision>) -> Self {
+        let revisions = revisions.into_iter().map(Into::into).collect();
+        Self { revisions }
+    }
+}
+impl fmt::Display for CacheListOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.revisions.len();
+        if count == 0 {
+            return write!(f, "Found no cached revisions");
+        }
+
+        let total_size = self.revisions.iter().map(|r| r.size).sum();
+        write!(
+            f,
+            "Found {count} cached {revision_form} ({})",
+            format_size(total_size),
+            revision_form = if count == 1 { "revision" } else { "revisions" },
+        )?;
+        for revision in self.revisions.iter() {
+            write!(f, "\n    {revision}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Structured output data of the revisions removed from the local cache.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CacheRemovalOutput {
+    /// The removed revisions.
+    pub removed: Vec<CachedRevisionOutput>,
+}
+impl From<Vec<CachedRevision>> for CacheRemovalOutput {
+    fn from(removed: Vec<CachedRevision>) -> Self {
+        let removed = removed.into_iter().map(Into::into).collect();
+        Self { removed }
+    }
+}
+impl fmt::Display for CacheRemovalOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.removed.len();
+        if count == 0 {
+            return write!(f, "Removed no cached revisions");
+        }
+
+        let total_size = self.removed.iter().map(|r| r.size).sum();
+        write!(
+            f,
+            "Removed {count} cached {revision_form}, freeing {}",
+            format_size(total_size),
+            revision_form = if count == 1 { "revision" } else { "revisions" },
+        )?;
+        for revision in self.removed.iter() {
+            write!(f, "\n    {revision}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A revision in the local cache.
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CachedRevisionOutput {
+    /// The revision ID of the page.
+    pub revision: u64,
+    /// The total size of all cache files of this revision, in bytes.
+    pub size: u64,
+    /// When the cache files of this revision were last modified.
+    #[serde_as(as = "TimestampSeconds<i64>")]
+    pub modified: SystemTime,
+}
+impl From<CachedRevision> for CachedRevisionOutput {
+    fn from(cached: CachedRevision) -> Self {
+        let CachedRevision { revision, files: _, size, modified } = cached;
+        Self { revision, size, modified }
+    }
+}
+impl fmt::Display for CachedRevisionOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { revision, size, modified } = self;
+        write!(
+            f,
+            "{:>12}  {:>10}  {}",
+            color!(revision, Green),
+            format_size(*size),
+            color!(humantime::format_rfc3339_seconds(*modified), Blue),
+        )
+    }
+}
+
+/// Structured output data of re-parsing the revisions in the local cache.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CacheVerifyOutput {
+    /// The result of each revision.
+    pub results: Vec<RevisionVerification>,
+}
+impl fmt::Display for CacheVerifyOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.results.len();
+        if count == 0 {
+            return write!(f, "Found no cached revisions to verify");
+        }
+
+        let failed = self.results.iter().filter(|r| r.error.is_some()).count();
+        write!(
+            f,
+            "Verified {count} cached {revision_form}, {} failed",
+            if failed == 0 {
+                color!(failed, Green)
+            } else {
+                color!(failed, Red)
+            },
+            revision_form = if count == 1 { "revision" } else { "revisions" },
+        )?;
+        for result in self.results.iter() {
+            write!(f, "\n    {result}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The result of re-parsing a single cached revision.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RevisionVerification {
+    /// The revision ID of the page.
+    pub revision: u64,
+    /// The number of parsed use cases.
+    pub use_cases: usize,
+    /// The number of description spans that could not be fully parsed.
+    pub unknown_spans: usize,
+    /// Why the revision could not be parsed, if it failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+impl fmt::Display for RevisionVerification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            revision,
+            use_cases,
+            unknown_spans,
+            error,
+        } = self;
+
+        write!(f, "{:>12}: ", color!(revision, Green))?;
+        if let Some(err) = error {
+            return write!(f, "{} {err}", color!("failed:", Red));
         }
+        write!(f, "{} {use_cases} use cases", color!("ok:", Green))?;
+        if *unknown_spans > 0 {
+            write!(
+                f,
+                ", {}",
+                color!(format!("{unknown_spans} unknown description spans"), Yellow)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Format a size in bytes in human-readable form.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next_unit in UNITS.iter().skip(1) {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next_unit;
+    }
+    format!("{size:.1} {unit}")
+}
+
+/// A line of input annotated with the best known use case of each port in it.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AnnotatedLineOutput<'a> {
+    /// The line of input.
+    pub line: String,
+    /// The ports found in the line, in order of appearance.
+    pub annotations: Vec<PortAnnotation<'a>>,
+}
+impl fmt::Display for AnnotatedLineOutput<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { line, annotations } = self;
 
+        write!(f, "{line}")?;
+        let annotations_str = annotations
+            .iter()
+            .filter_map(|a| {
+                let use_case = a.use_case.as_ref()?;
+                Some(format!(
+                    "{}: {}",
+                    color!(a.port, Green),
+                    use_case.format_description()
+                ))
+            })
+            .join("; ");
+        if !annotations_str.is_empty() {
+            write!(f, "  {} {annotations_str}", color!("#", BrightBlack))?;
+        }
         Ok(())
     }
 }
 
+/// The best known use case of a port found in a line of input.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PortAnnotation<'a> {
+    /// The port found in the line.
+    pub port: PortSelection,
+    /// Where the use case came from, if one is known.
+    pub source: Option<&'a DataSource>,
+    /// The best known use case, if any.
+    pub use_case: Option<PortUseCase<'a>>,
+}
+
+/// Colorise a port type.
+fn format_port_type(port_type: PortType) -> String {
+    yansi::Paint::fg(&port_type, port_type_color(port_type)).to_string()
+}
+
+/// The colour to show a port type in.
+pub fn port_type_color(port_type: PortType) -> yansi::Color {
+    use yansi::Color as C;
+    use PortType as T;
+
+    match port_type {
+        T::Unused => C::Primary,
+        T::Yes => C::Green,
+        T::Unofficial => C::Cyan,
+        T::Assigned => C::Yellow,
+        T::No => C::Red,
+        T::Reserved => C::BrightBlack,
+    }
+}
+
+/// Write the optional sections of a list of changes.
+fn write_change_sections<'a>(
+    f: &mut fmt::Formatter<'_>,
+    changes: impl IntoIterator<Item = &'a PortChange<'a>>,
+) -> fmt::Result {
+    let use_cases = changes
+        .into_iter()
+        .flat_map(PortChange::use_cases)
+        .collect_vec();
+
+    let links = use_cases
+        .iter()
+        .flat_map(|u| u.format_links())
+        .collect_vec();
+    if !links.is_empty() {
+        let lines = links.iter().map(|line| format!("    {line}")).join("\n");
+        write!(f, "\n\nLinks:\n{lines}")?;
+    }
+
+    // the same note or reference may be shown by both sides of a change
+    let notes_and_refs = use_cases
+        .iter()
+        .flat_map(|u| u.format_notes_and_refs())
+        .unique()
+        .collect_vec();
+    if !notes_and_refs.is_empty() {
+        let lines = notes_and_refs
+            .iter()
+            .map(|line| format!("    {line}"))
+            .join("\n");
+        write!(f, "\n\nNotes and References:\n{lines}")?;
+    }
+
+    Ok(())
+}
+
+/// Indent each non-empty line of a multi-line string.
+fn indent_lines(s: &str) -> String {
+    s.lines()
+        .map(|line| {
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!("    {line}")
+            }
+        })
+        .join("\n")
+}
+
+/// Write a list of matched ports, followed by the optional sections.
+///
+/// `case_adjective` describes the use cases in each subtitle, e.g. "matched".
+fn write_matched_ports(
+    f: &mut fmt::Formatter<'_>,
+    matched: &[MatchedPort<'_>],
+    case_adjective: &str,
+) -> fmt::Result {
+    let matched_str = matched
+        .iter()
+        .map(|p| {
+            let category = PortCategory::from(*p.number.start());
+            let case_count = p.use_cases.len();
+
+            let subtitle = if p.number.clone().count() == 1 {
+                format!(
+                    "Port {p} is a {c} port with {case_count} {case_adjective} use {case_form}",
+                    p = color!(p.number.start(), Green),
+                    c = color!(category, Blue),
+                    case_form = if case_count == 1 { "case" } else { "cases" },
+                )
+            } else {
+                format!(
+                    "Port {p} are {c} ports with {case_count} {case_adjective} use {case_form}",
+                    p = color!(format!("{}-{}", p.number.start(), p.number.end()), Green),
+                    c = color!(category, Blue),
+                    case_form = if case_count == 1 { "case" } else { "cases" },
+                )
+            };
+            let use_cases_str = p.format_use_cases(true, Some("    "), "\n");
+            format!("{subtitle}\n{use_cases_str}")
+        })
+        .join("\n\n");
+    write!(f, "{matched_str}")?;
+
+    let links = matched
+        .iter()
+        .flat_map(MatchedPort::format_links)
+        .collect_vec();
+    if !links.is_empty() {
+        let lines = links.iter().map(|line| format!("    {line}")).join("\n");
+        write!(f, "\n\nLinks:\n{lines}")?;
+    }
+
+    let notes_and_refs = matched
+        .iter()
+        .flat_map(MatchedPort::format_notes_and_refs)
+        .collect_vec();
+    if !notes_and_refs.is_empty() {
+        let lines = notes_and_refs
+            .iter()
+            .map(|line| format!("    {line}"))
+            .join("\n");
+        write!(f, "\n\nNotes and References:\n{lines}")?;
+    }
+
+    Ok(())
+}
+
 /// Information on a matched port.
 ///
 /// The parent struct implementation decides how to display this info.
 #[derive(Clone, Debug, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct MatchedPort<'a> {
+    /// The port or range of ports.
     pub number: RangeInclusive<u16>,
+    /// All matched use cases.
     pub use_cases: Vec<PortUseCase<'a>>,
 }
 impl MatchedPort<'_> {
@@ -221,6 +1091,157 @@ impl MatchedPort<'_> {
     }
 }
 
+/// A segment of a rich description, rendered according to user options.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StyledSegment {
+    /// The displayed text.
+    pub text: String,
+    /// The colour of the text, if any.
+    pub color: Option<yansi::Color>,
+    /// Where the text links to, if anywhere.
+    ///
+    /// Linked text is shown in italics.
+    pub url: Option<String>,
+}
+impl StyledSegment {
+    fn plain(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            color: None,
+            url: None,
+        }
+    }
+
+    fn colored(text: impl Into<String>, color: yansi::Color) -> Self {
+        Self {
+            text: text.into(),
+            color: Some(color),
+            url: None,
+        }
+    }
+
+    fn linked(text: impl Into<String>, color: yansi::Color, url: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            color: Some(color),
+            url: Some(url.into()),
+        }
+    }
+}
+impl fmt::Display for StyledSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use yansi::Paint;
+
+        let Self { text, color, url } = self;
+        let Some(color) = color else {
+            return write!(f, "{text}");
+        };
+        let style = yansi::Style::new().fg(*color);
+        match url {
+            Some(url) => write!(f, "{}", text.paint(style.italic()).link(url)),
+            None => write!(f, "{}", text.paint(style)),
+        }
+    }
+}
+
+/// A rich description rendered according to user options.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderedRichText {
+    /// The segments of the description line.
+    pub description: Vec<StyledSegment>,
+    /// The links in the description, if links are shown.
+    ///
+    /// Format: `(tag, url)`.
+    pub links: Vec<(StyledSegment, String)>,
+    /// The notes and references in the description, if they are shown.
+    ///
+    /// Format: `(tag, url)`.
+    pub notes_and_refs: Vec<(StyledSegment, String)>,
+}
+
+/// Render a rich description into styled segments.
+///
+/// `show_links` expects a starting index if links are to be shown.
+pub fn render_rich_text(
+    spans: &[RichTextSpan],
+    urls: &SourceUrls,
+    mut show_links: Option<usize>,
+    show_notes_and_references: bool,
+) -> RenderedRichText {
+    use yansi::Color as C;
+    use RichTextSpan as Span;
+
+    let mut description = vec![];
+    let mut links = vec![];
+    let mut notes_and_refs = vec![];
+
+    let mut push_link = |description: &mut Vec<StyledSegment>, text: &str, color, url: String| {
+        if let Some(idx) = show_links.as_mut() {
+            let tag = format!("[{idx}]");
+            *idx += 1;
+            description.push(StyledSegment::linked(format!("{text}{tag}"), color, &url));
+            links.push((StyledSegment::colored(tag, color), url));
+        } else {
+            description.push(StyledSegment::linked(text, color, url));
+        }
+    };
+    let mut push_tag = |description: &mut Vec<StyledSegment>, tag: String, url: String| {
+        if show_notes_and_references {
+            description.push(StyledSegment::linked(&tag, C::Yellow, &url));
+            notes_and_refs.push((StyledSegment::colored(tag, C::Yellow), url));
+        }
+    };
+
+    for span in spans {
+        match span {
+            Span::Text { text } => {
+                description.push(StyledSegment::plain(text));
+            }
+            Span::Abbreviation { short, long } => {
+                description.push(StyledSegment::plain(short));
+                if let Some(long) = long {
+                    description.push(StyledSegment::plain(format!(" ({long})")));
+                }
+            }
+            Span::Code { text } => {
+                description.push(StyledSegment::plain(format!("`{text}`")));
+            }
+            Span::SiteLink { text, link } => {
+                let url = format!("{}{link}", urls.origin_base);
+                push_link(&mut description, text, C::Cyan, url);
+            }
+            Span::SiteLinkNonExistent { text, link } => {
+                let url = format!("{}{link}", urls.origin_base);
+                push_link(&mut description, text, C::Red, url);
+            }
+            Span::ExternalLink { text, link } => {
+                push_link(&mut description, text, C::Cyan, link.to_owned());
+            }
+            Span::Note { number, note_id } => {
+                let url = format!("{}#{note_id}", urls.page);
+                push_tag(&mut description, format!("[note {number}]"), url);
+            }
+            Span::Reference { number, ref_id } => {
+                let url = format!("{}#{ref_id}", urls.page);
+                push_tag(&mut description, format!("[ref {number}]"), url);
+            }
+            Span::Annotation { text, link } => {
+                let url = format!("{}{link}", urls.origin_base);
+                // currently annotation text already contains delimiting brackets
+                push_tag(&mut description, text.clone(), url);
+            }
+            Span::Subscript { text } => {
+                description.push(StyledSegment::plain(format!("_{{{text}}}"))); // LaTeX syntax
+            }
+            Span::Unknown { text, err: _ } => {
+                description.push(StyledSegment::plain(text));
+            }
+        }
+    }
+
+    RenderedRichText { description, links, notes_and_refs }
+}
+
 /// A single use case for a user-specified port.
 ///
 /// This struct is intended for direct output, therefore the information about
@@ -255,6 +1276,9 @@ pub struct PortUseCase<'a> {
     ///
     /// This is useful for JSON output.
     rich_description: &'a [RichTextSpan],
+    /// Registration details, only available from the IANA registry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    registration: Option<&'a IanaRegistration>,
 }
 impl<'a> PortUseCase<'a> {
     /// Create an instance of [`PortUseCase`] by applying user options.
@@ -262,108 +1286,32 @@ impl<'a> PortUseCase<'a> {
     /// `show_links` expects a starting index if links are to be shown.
     pub fn from_with_options(
         from: &'a PortRangeInfo,
-        mut show_links: Option<usize>,
+        urls: &SourceUrls,
+        show_links: Option<usize>,
         show_notes_and_references: bool,
     ) -> Self {
-        use RichTextSpan as Span;
-
-        let mut description = String::new();
-        let mut links = vec![];
-        let mut notes_and_refs = vec![];
-
-        for span in from.rich_description.iter() {
-            match span {
-                Span::Text { text } => {
-                    description.push_str(text);
-                }
-                Span::Abbreviation { short, long } => {
-                    description.push_str(short);
-                    if let Some(long) = long {
-                        description.push_str(&format!(" ({long})"));
-                    }
-                }
-                Span::Code { text } => {
-                    description.push_str(&format!("`{text}`"));
-                }
-                Span::SiteLink { text, link } => {
-                    let url = format!("{ORIGIN_BASE_URL}{link}");
-                    if let Some(idx) = show_links.as_mut() {
-                        let tag = format!("[{idx}]");
-                        *idx += 1;
-                        description
-                            .push_str(&hyperlink!(format!("{text}{tag}"), Cyan, &url).to_string());
-                        links.push((color!(tag, Cyan).to_string(), url));
-                    } else {
-                        description.push_str(&hyperlink!(text, Cyan, &url).to_string());
-                    }
-                }
-                Span::SiteLinkNonExistent { text, link } => {
-                    let url = format!("{ORIGIN_BASE_URL}{link}");
-                    if let Some(idx) = show_links.as_mut() {
-                        let tag = format!("[{idx}]");
-                        *idx += 1;
-                        description
-                            .push_str(&hyperlink!(format!("{text}{tag}"), Red, &url).to_string());
-                        links.push((color!(tag, Red).to_string(), url));
-                    } else {
-                        description.push_str(&hyperlink!(text, Red, &url).to_string());
-                    }
-                }
-                Span::ExternalLink { text, link } => {
-                    let url = link.to_owned();
-                    if let Some(idx) = show_links.as_mut() {
-                        let tag = format!("[{idx}]");
-                        *idx += 1;
-                        description
-                            .push_str(&hyperlink!(format!("{text}{tag}"), Cyan, &url).to_string());
-                        links.push((color!(tag, Cyan).to_string(), url));
-                    } else {
-                        description.push_str(&hyperlink!(text, Cyan, &url).to_string());
-                    }
-                }
-                Span::Note { number, note_id } => {
-                    if show_notes_and_references {
-                        let url = format!("{PAGE_URL}#{note_id}");
-                        let tag = format!("[note {number}]");
-                        description.push_str(&hyperlink!(tag, Yellow, &url).to_string());
-                        notes_and_refs.push((color!(tag, Yellow).to_string(), url));
-                    }
-                }
-                Span::Reference { number, ref_id } => {
-                    if show_notes_and_references {
-                        let url = format!("{PAGE_URL}#{ref_id}");
-                        let tag = format!("[ref {number}]");
-                        description.push_str(&hyperlink!(tag, Yellow, &url).to_string());
-                        notes_and_refs.push((color!(tag, Yellow).to_string(), url));
-                    }
-                }
-                Span::Annotation { text, link } => {
-                    if show_notes_and_references {
-                        let url = format!("{ORIGIN_BASE_URL}{link}");
-                        // currently annotation text already contains delimiting brackets
-                        let tag = text.clone();
-                        description.push_str(&hyperlink!(tag, Yellow, &url).to_string());
-                        notes_and_refs.push((color!(tag, Yellow).to_string(), url));
-                    }
-                }
-                Span::Subscript { text } => {
-                    description.push_str(&format!("_{{{text}}}")); // LaTeX syntax
-                }
-                Span::Unknown { text, err: _ } => {
-                    description.push_str(text);
-                }
-            }
-        }
+        let RenderedRichText { description, links, notes_and_refs } = render_rich_text(
+            &from.rich_description,
+            urls,
+            show_links,
+            show_notes_and_references,
+        );
+        let tags_to_strings = |list: Vec<(StyledSegment, String)>| {
+            list.into_iter()
+                .map(|(tag, url)| (tag.to_string(), url))
+                .collect()
+        };
 
         Self {
             tcp: from.tcp_type,
             udp: from.udp_type,
             sctp: from.sctp_type,
             dccp: from.dccp_type,
-            description,
-            links,
-            notes_and_refs,
+            description: description.iter().join(""),
+            links: tags_to_strings(links),
+            notes_and_refs: tags_to_strings(notes_and_refs),
             rich_description: &from.rich_description,
+            registration: from.registration.as_ref(),
         }
     }
 
@@ -375,37 +1323,47 @@ impl<'a> PortUseCase<'a> {
     }
 
     /// Format the description line.
-    fn format_description(&self) -> String {
+    pub fn format_description(&self) -> String {
         self.description.clone()
     }
 
+    /// The port type of each protocol, with labels.
+    fn labelled_types(&self) -> [(&'static str, PortType); 4] {
+        [
+            ("TCP", self.tcp),
+            ("UDP", self.udp),
+            ("SCTP", self.sctp),
+            ("DCCP", self.dccp),
+        ]
+    }
+
     /// Format the protocol line.
     fn format_protocols(&self) -> String {
-        use PortType as T;
-        let Self { tcp, udp, sctp, dccp, .. } = self;
-
-        let mut buf = vec![];
-        macro_rules! push_proto {
-            ($proto: ident, $label: expr) => {
-                let proto_str = match $proto {
-                    T::Unused => None, // skip
-                    T::Yes => Some(format!("{}: {}", $label, color!($proto, Green))),
-                    T::Unofficial => Some(format!("{}: {}", $label, color!($proto, Cyan))),
-                    T::Assigned => Some(format!("{}: {}", $label, color!($proto, Yellow))),
-                    T::No => Some(format!("{}: {}", $label, color!($proto, Red))),
-                    T::Reserved => Some(format!("{}: {}", $label, color!($proto, BrightBlack))),
-                };
-                if let Some(s) = proto_str {
-                    buf.push(s);
-                }
-            };
-        }
-        push_proto!(tcp, "TCP");
-        push_proto!(udp, "UDP");
-        push_proto!(sctp, "SCTP");
-        push_proto!(dccp, "DCCP");
+        self.labelled_types()
+            .into_iter()
+            .filter(|(_, t)| !t.is_unused())
+            .map(|(label, t)| format!("{label}: {}", format_port_type(t)))
+            .join(", ")
+    }
 
-        buf.join(", ")
+    /// Format the protocol line, showing the changes from an older use case.
+    fn format_protocol_changes(&self, old: &Self) -> String {
+        old.labelled_types()
+            .into_iter()
+            .zip(self.labelled_types())
+            .filter(|((_, old_t), (_, new_t))| !(old_t.is_unused() && new_t.is_unused()))
+            .map(|((label, old_t), (_, new_t))| {
+                if old_t == new_t {
+                    format!("{label}: {}", format_port_type(new_t))
+                } else {
+                    format!(
+                        "{label}: {} -> {}",
+                        format_port_type(old_t),
+                        format_port_type(new_t)
+                    )
+                }
+            })
+            .join(", ")
     }
 
     /// Format lines of the optional link section.
@@ -426,3 +1384,70 @@ impl<'a> PortUseCase<'a> {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans() -> Vec<RichTextSpan> {
+        vec![
+            RichTextSpan::SiteLink {
+                text: "Secure Shell".into(),
+                link: "/wiki/Secure_Shell".into(),
+            },
+            RichTextSpan::Text { text: " (SSH)".into() },
+            RichTextSpan::Reference { number: 1, ref_id: "cite_note-1".into() },
+        ]
+    }
+
+    fn urls() -> SourceUrls {
+        SourceUrls {
+            page: "https://wiki.example/page".into(),
+            origin_base: "https://wiki.example".into(),
+            ..SourceUrls::default()
+        }
+    }
+
+    #[test]
+    fn hides_tags_by_default() {
+        let rendered = render_rich_text(&spans(), &urls(), None, false);
+        let expected = vec![
+            StyledSegment::linked(
+                "Secure Shell",
+                yansi::Color::Cyan,
+                "https://wiki.example/wiki/Secure_Shell",
+            ),
+            StyledSegment::plain(" (SSH)"),
+        ];
+        assert_eq!(rendered.description, expected);
+        assert!(rendered.links.is_empty());
+        assert!(rendered.notes_and_refs.is_empty());
+    }
+
+    #[test]
+    fn numbers_links_from_starting_index() {
+        let rendered = render_rich_text(&spans(), &urls(), Some(3), false);
+        assert_eq!(rendered.description[0].text, "Secure Shell[3]");
+        let expected = vec![(
+            StyledSegment::colored("[3]", yansi::Color::Cyan),
+            "https://wiki.example/wiki/Secure_Shell".to_owned(),
+        )];
+        assert_eq!(rendered.links, expected);
+    }
+
+    #[test]
+    fn shows_notes_and_references() {
+        let rendered = render_rich_text(&spans(), &urls(), None, true);
+        let reference = StyledSegment::linked(
+            "[ref 1]",
+            yansi::Color::Yellow,
+            "https://wiki.example/page#cite_note-1",
+        );
+        assert_eq!(rendered.description.last(), Some(&reference));
+        let expected = vec![(
+            StyledSegment::colored("[ref 1]", yansi::Color::Yellow),
+            "https://wiki.example/page#cite_note-1".to_owned(),
+        )];
+        assert_eq!(rendered.notes_and_refs, expected);
+    }
+}
@@ -0,0 +1,219 @@
+//! The HTTP client used to retrieve sources.
+
+use std::{path::PathBuf, time::Duration};
+
+use color_eyre::eyre::{bail, Context};
+use log::{debug, warn};
+use reqwest::{header::HeaderMap, Certificate, NoProxy, Proxy, Response, StatusCode};
+use tokio::fs;
+
+use crate::consts::USER_AGENT;
+
+/// Options of the HTTP client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpOptions {
+    /// The timeout of each attempt of a request.
+    pub timeout: Duration,
+    /// How many times a failed request is retried.
+    pub retries: u32,
+    /// The delay before the first retry, doubled for each following retry.
+    pub retry_delay: Duration,
+    /// A proxy to use for all requests instead of the one in the environment.
+    pub proxy: Option<String>,
+    /// A PEM file of additional CA certificates to trust.
+    pub ca_bundle: Option<PathBuf>,
+}
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            retries: 2,
+            retry_delay: Duration::from_secs(1),
+            proxy: None,
+            ca_bundle: None,
+        }
+    }
+}
+
+/// An HTTP client that retries failed requests.
+#[derive(Clone, Debug)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    retries: u32,
+    retry_delay: Duration,
+}
+impl HttpClient {
+    /// Build a client with the given options.
+    ///
+    /// Errors if the proxy URL or the CA bundle is invalid.
+    pub async fn new(options: &HttpOptions) -> color_eyre::Result<Self> {
+        let HttpOptions {
+            timeout,
+            retries,
+            retry_delay,
+            proxy,
+            ca_bundle,
+        } = options;
+
+        // `HTTPS_PROXY`, `NO_PROXY` etc. are respected by default
+        let mut builder = reqwest::ClientBuilder::new()
+            .user_agent(USER_AGENT)
+            .connection_verbose(true)
+            .timeout(*timeout);
+        if let Some(proxy) = proxy {
+            let proxy = Proxy::all(proxy)
+                .wrap_err_with(|| format!("Invalid proxy URL {proxy:?}"))?
+                .no_proxy(NoProxy::from_env());
+            builder = builder.proxy(proxy);
+        }
+        if let Some(path) = ca_bundle {
+            let pem = fs::read(path)
+                .await
+                .wrap_err_with(|| format!("Failed to read CA bundle at {path:?}"))?;
+            let certs = Certificate::from_pem_bundle(&pem)
+                .wrap_err_with(|| format!("Invalid CA bundle at {path:?}"))?;
+            if certs.is_empty() {
+                bail!("Found no certificates in CA bundle at {path:?}");
+            }
+            builder = builder.tls_certs_merge(certs);
+        }
+        let client = builder
+            .build()
+            .wrap_err("Failed to initialise HTTP client")?;
+
+        Ok(Self {
+            client,
+            retries: *retries,
+            retry_delay: *retry_delay,
+        })
+    }
+
+    /// Send a GET request, retrying on network errors and server errors.
+    ///
+    /// Errors if the final attempt fails or returns an error status.
+    pub async fn get(&self, url: &str, headers: HeaderMap) -> color_eyre::Result<Response> {
+        let mut delay = self.retry_delay;
+        let mut attempt = 0;
+        loop {
+            let res = self.client.get(url).headers(headers.clone()).send().await;
+            let retry_reason = match &res {
+                Ok(res) if is_retryable_status(res.status()) => res.status().to_string(),
+                Err(err) if err.is_connect() || err.is_timeout() => err.to_string(),
+                _ => return Ok(res?.error_for_status()?),
+            };
+            if attempt >= self.retries {
+                debug!("Giving up on {url} after {} attempts", attempt + 1);
+                return Ok(res?.error_for_status()?);
+            }
+
+            warn!("Request to {url} failed ({retry_reason}), retrying in {delay:?}");
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+/// Whether a request that returned this status is worth retrying.
+fn is_retryable_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    async fn client(retries: u32) -> HttpClient {
+        let options = HttpOptions {
+            timeout: Duration::from_millis(200),
+            retries,
+            retry_delay: Duration::from_millis(20),
+            ..Default::default()
+        };
+        HttpClient::new(&options).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/page"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/page"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let start = Instant::now();
+        let res = client(2)
+            .await
+            .get(&format!("{}/page", server.uri()), HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(res.text().await.unwrap(), "ok");
+        // backoff of 20ms, then 40ms
+        assert!(start.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[tokio::test]
+    async fn retries_timeouts() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(1)))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let res = client(1)
+            .await
+            .get(&server.uri(), HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(res.text().await.unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let res = client(2).await.get(&server.uri(), HeaderMap::new()).await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let res = client(2).await.get(&server.uri(), HeaderMap::new()).await;
+        assert!(res.is_err());
+    }
+}
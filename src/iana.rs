@@ -0,0 +1,313 @@
+//! Parsing the IANA Service Name and Transport Protocol Port Number Registry.
+
+use std::{io::Read, ops::RangeInclusive};
+
+use color_eyre::eyre::Context;
+use itertools::Itertools;
+use log::{trace, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    parse::RichTextSpan,
+    store::{DataSource, PortDatabase, PortRangeInfo, PortType},
+};
+
+/// A single row of the IANA registry in CSV form.
+///
+/// See <https://www.iana.org/assignments/service-names-port-numbers/>.
+#[derive(Clone, Debug, Deserialize)]
+struct IanaRecord {
+    #[serde(rename = "Service Name")]
+    service_name: String,
+    #[serde(rename = "Port Number")]
+    port_number: String,
+    #[serde(rename = "Transport Protocol")]
+    transport_protocol: String,
+    #[serde(rename = "Description")]
+    description: String,
+    #[serde(rename = "Assignee")]
+    assignee: String,
+    #[serde(rename = "Contact")]
+    contact: String,
+    #[serde(rename = "Registration Date")]
+    registration_date: String,
+    #[serde(rename = "Modification Date")]
+    modification_date: String,
+    #[serde(rename = "Reference")]
+    reference: String,
+}
+
+/// Registration details of a port, as recorded by IANA.
+///
+/// Empty fields in the registry are represented as `None`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IanaRegistration {
+    /// The registered service name, e.g. `ssh`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
+    /// Who the port is assigned to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assignee: Option<String>,
+    /// Who to contact about the registration.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+    /// When the port was registered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registration_date: Option<String>,
+    /// When the registration was last modified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modification_date: Option<String>,
+    /// Where the service is specified, e.g. an RFC.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+}
+impl From<&IanaRecord> for IanaRegistration {
+    fn from(record: &IanaRecord) -> Self {
+        let non_empty = |s: &str| {
+            let s = s.trim();
+            (!s.is_empty()).then(|| s.to_owned())
+        };
+        Self {
+            service_name: non_empty(&record.service_name),
+            assignee: non_empty(&record.assignee),
+            contact: non_empty(&record.contact),
+            registration_date: non_empty(&record.registration_date),
+            modification_date: non_empty(&record.modification_date),
+            reference: non_empty(&record.reference),
+        }
+    }
+}
+impl IanaRegistration {
+    /// Generate a rich description, so that the registration can be
+    /// displayed and searched like a Wikipedia entry.
+    fn rich_description(&self, description: &str) -> Vec<RichTextSpan> {
+        use RichTextSpan as Span;
+
+        let mut spans = vec![];
+        if let Some(name) = &self.service_name {
+            spans.push(Span::Code { text: name.clone() });
+            spans.push(Span::Text { text: " - ".into() });
+        }
+        spans.push(Span::Text { text: description.trim().into() });
+
+        let details = [
+            ("assignee", &self.assignee),
+            ("registered", &self.registration_date),
+            ("modified", &self.modification_date),
+        ]
+        .into_iter()
+        .filter_map(|(label, value)| value.as_ref().map(|v| format!("{label}: {v}")))
+        .join(", ");
+        if !details.is_empty() {
+            spans.push(Span::Text { text: format!(" ({details})") });
+        }
+
+        if let Some(reference) = &self.reference {
+            spans.push(Span::Text { text: " ".into() });
+            spans.extend(parse_reference(reference));
+        }
+
+        spans
+    }
+}
+
+/// Parse the reference field, e.g. `[RFC4251][Tatu_Ylonen]`.
+///
+/// RFC references are turned into links; everything else is kept as text.
+fn parse_reference(reference: &str) -> Vec<RichTextSpan> {
+    use RichTextSpan as Span;
+
+    reference
+        .split_inclusive(']')
+        .map(|item| {
+            let inner = item.trim().trim_start_matches('[').trim_end_matches(']');
+            match inner
+                .strip_prefix("RFC")
+                .and_then(|n| n.parse::<u32>().ok())
+            {
+                Some(number) => Span::ExternalLink {
+                    text: inner.into(),
+                    link: format!("https://www.rfc-editor.org/rfc/rfc{number}"),
+                },
+                None => Span::Text { text: item.trim().into() },
+            }
+        })
+        .collect()
+}
+
+/// Parse the port number field, which is either a single port or a range.
+fn parse_port_number(s: &str) -> color_eyre::Result<RangeInclusive<u16>> {
+    let range = match s.split_once('-') {
+        Some((start, end)) => start.trim().parse()?..=end.trim().parse()?,
+        None => {
+            let port = s.trim().parse()?;
+            port..=port
+        }
+    };
+    Ok(range)
+}
+
+/// Parse the IANA Service Name and Transport Protocol Port Number Registry
+/// from its CSV form.
+///
+/// Registry entries that only differ in transport protocol are merged into a
+/// single use case. Unassigned ports and service names without a port are
+/// skipped.
+pub fn parse_iana_registry(csv: impl Read) -> color_eyre::Result<PortDatabase> {
+    let mut reader = csv::Reader::from_reader(csv);
+
+    let mut list: Vec<(PortRangeInfo, String)> = vec![];
+    for record in reader.deserialize() {
+        let record: IanaRecord = record.wrap_err("Malformed IANA registry")?;
+
+        if record.port_number.trim().is_empty() {
+            continue; // service name without an assigned port
+        }
+        if record.description.trim() == "Unassigned" {
+            continue;
+        }
+        let number = match parse_port_number(&record.port_number) {
+            Ok(n) => n,
+            Err(err) => {
+                warn!(
+                    r#"Skipping IANA entry with bad port number "{}": {err}"#,
+                    record.port_number
+                );
+                continue;
+            }
+        };
+        let protocol = match record.transport_protocol.trim() {
+            p @ ("tcp" | "udp" | "sctp" | "dccp") => Some(p),
+            "" => None,
+            p => {
+                warn!(r#"Skipping IANA entry with unknown transport protocol "{p}""#);
+                continue;
+            }
+        };
+        let port_type = if record.description.trim() == "Reserved" {
+            PortType::Reserved
+        } else {
+            PortType::Assigned
+        };
+
+        // merge with the previous entry if only the protocol differs
+        // the registry lists different protocols of the same service consecutively
+        let registration = IanaRegistration::from(&record);
+        let mergeable = list.last().is_some_and(|(info, description)| {
+            info.number == number
+                && info.registration.as_ref() == Some(&registration)
+                && description == &record.description
+        });
+        if !mergeable {
+            let info = PortRangeInfo {
+                number,
+                tcp_type: PortType::Unused,
+                udp_type: PortType::Unused,
+                sctp_type: PortType::Unused,
+                dccp_type: PortType::Unused,
+                rich_description: registration.rich_description(&record.description),
+                service_names: registration.service_name.iter().cloned().collect(),
+                registration: Some(registration),
+            };
+            list.push((info, record.description.clone()));
+        }
+        let (info, _) = list.last_mut().unwrap();
+        match protocol {
+            Some("tcp") => info.tcp_type = port_type,
+            Some("udp") => info.udp_type = port_type,
+            Some("sctp") => info.sctp_type = port_type,
+            Some("dccp") => info.dccp_type = port_type,
+            _ => trace!(
+                "IANA entry {} has no transport protocol",
+                record.port_number
+            ),
+        }
+    }
+
+    let ports = list.into_iter().map(|(info, _)| info).collect();
+    Ok(PortDatabase {
+        source: DataSource::Iana,
+        ports,
+        urls: Default::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "Service Name,Port Number,Transport Protocol,Description,Assignee,Contact,Registration Date,Modification Date,Reference,Service Code,Unauthorized Use Reported,Assignment Notes\n";
+
+    fn parse(rows: &str) -> color_eyre::Result<PortDatabase> {
+        parse_iana_registry(format!("{HEADER}{rows}").as_bytes())
+    }
+
+    #[test]
+    fn merges_protocols_of_a_service() {
+        let db = parse(
+            "ssh,22,tcp,The Secure Shell (SSH) Protocol,,,,,[RFC4251],,,\n\
+             ssh,22,udp,The Secure Shell (SSH) Protocol,,,,,[RFC4251],,,\n",
+        )
+        .unwrap();
+
+        assert_eq!(db.source, DataSource::Iana);
+        let [ssh] = &db.ports[..] else {
+            panic!("expected a single use case, got {:?}", db.ports);
+        };
+        assert_eq!(ssh.number, 22..=22);
+        assert_eq!(ssh.tcp_type, PortType::Assigned);
+        assert_eq!(ssh.udp_type, PortType::Assigned);
+        assert_eq!(ssh.sctp_type, PortType::Unused);
+        assert_eq!(ssh.service_names, ["ssh"]);
+        assert!(ssh.rich_description.iter().any(|span| matches!(
+            span,
+            RichTextSpan::ExternalLink { text, link }
+                if text == "RFC4251" && link == "https://www.rfc-editor.org/rfc/rfc4251"
+        )));
+    }
+
+    #[test]
+    fn skips_unassigned_ranges() {
+        let db = parse(
+            ",23-24,tcp,Unassigned,,,,,,,,\n\
+             x11,6000-6063,tcp,X Window System,[Stephen_Casner],,,2017-05-18,,,,\n",
+        )
+        .unwrap();
+
+        let [x11] = &db.ports[..] else {
+            panic!("expected a single use case, got {:?}", db.ports);
+        };
+        assert_eq!(x11.number, 6000..=6063);
+        let registration = x11.registration.as_ref().unwrap();
+        assert_eq!(registration.assignee.as_deref(), Some("[Stephen_Casner]"));
+        assert_eq!(
+            registration.modification_date.as_deref(),
+            Some("2017-05-18")
+        );
+        assert_eq!(registration.contact, None);
+    }
+
+    #[test]
+    fn keeps_entries_without_service_name() {
+        let db = parse(",0,tcp,Reserved,[Jon_Postel],[Jon_Postel],,,,,,\n").unwrap();
+
+        let [reserved] = &db.ports[..] else {
+            panic!("expected a single use case, got {:?}", db.ports);
+        };
+        assert_eq!(reserved.tcp_type, PortType::Reserved);
+        assert!(reserved.service_names.is_empty());
+        assert_eq!(reserved.registration.as_ref().unwrap().service_name, None);
+    }
+
+    #[test]
+    fn header_only_is_empty() {
+        let db = parse("").unwrap();
+        assert!(db.ports.is_empty());
+    }
+
+    #[test]
+    fn malformed_row_is_error() {
+        assert!(parse("ssh,22,tcp\n").is_err());
+    }
+}
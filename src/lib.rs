@@ -0,0 +1,67 @@
+//! Quickly lookup what a port is used for.
+//!
+//! This is the library behind the `wtp` binary. The typical flow is:
+//!
+//! - **loading** the sources like `wtp` does, with its fallbacks and staleness
+//!   checks, with [`load::Loader`];
+//! - or loading a single source, either from the network or the local cache,
+//!   with the functions in [`source`], e.g. [`source::get_database_offline`];
+//! - or **parsing** a source you already have, with [`parse::parse_page`],
+//!   [`iana::parse_iana_registry`] or [`services::parse_services_file`];
+//! - **querying** the resulting [`store::PortDatabase`], e.g. with
+//!   [`store::PortDatabase::lookup`];
+//! - **rendering** the structured result, either as text with its
+//!   [`Display`](std::fmt::Display) implementation, or as JSON with `serde`.
+//!
+//! ```no_run
+//! use what_the_port::{
+//!     display::Output,
+//!     query::{PortSelection, SupportedProtocol},
+//!     source::get_database_offline,
+//!     store::QueryFilter,
+//! };
+//!
+//! # async fn run() -> color_eyre::Result<()> {
+//! let db = get_database_offline("/path/to/cache", None).await?;
+//! let lookup = PortSelection { number: 22, protocol: SupportedProtocol::Tcp };
+//! let output = db.lookup(lookup, &QueryFilter::default(), false, false);
+//!
+//! println!("{output}");
+//! println!("{}", serde_json::to_string(&Output::from(output))?);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The front ends of `wtp` are behind default features, so that library users
+//! can opt out of their dependencies with `default-features = false`:
+//!
+//! - `cli`: the `cli` module of command line arguments, required by `wtp`
+//! - `repl`: the `repl` module of the interactive prompt
+//! - `tui`: the `tui` module of the full-screen browser
+//! - `server`: the `server` module of the HTTP API server
+
+#![warn(missing_docs)]
+
+pub mod annotate;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod config;
+pub mod consts;
+pub mod display;
+pub mod http;
+pub mod iana;
+pub mod load;
+pub mod local;
+pub mod nmap;
+pub mod parse;
+pub mod query;
+#[cfg(feature = "repl")]
+pub mod repl;
+pub mod search;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod services;
+pub mod source;
+pub mod store;
+#[cfg(feature = "tui")]
+pub mod tui;
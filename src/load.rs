@@ -0,0 +1,302 @@
+//! Loading the data sources selected by the user, with the same fallbacks as `wtp`.
+
+use std::{path::PathBuf, time::Duration, time::SystemTime};
+
+use itertools::Itertools;
+use log::warn;
+
+#[cfg(feature = "bundled-snapshot")]
+use crate::source::get_snapshot_database;
+use crate::{
+    config::{default_cache_dir, Config, PullPolicy},
+    consts::{DEFAULT_SERVICES_PATH, DEFAULT_STALE_AFTER},
+    http::{HttpClient, HttpOptions},
+    query::DataSourceKind,
+    source::{
+        format_age, get_all_databases, get_database_offline, get_database_online,
+        get_iana_database_offline, get_iana_database_online, get_revision_check_time,
+        get_services_database, migrate_legacy_pages, BackgroundRefresh, SourceUrls,
+    },
+    store::{DataSource, PortDatabase, ServiceIndex},
+};
+
+/// Options for loading the data sources.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoadOptions {
+    /// Which data sources to load, in order.
+    pub sources: Vec<DataSourceKind>,
+    /// Which revision of the Wikipedia page to load.
+    ///
+    /// If absent, the latest revision is used.
+    pub revision: Option<u64>,
+    /// Whether to retrieve sources from the network.
+    pub pull: bool,
+    /// Whether to retrieve the latest revision in the background if the
+    /// cached revision is stale. Only applies when not pulling.
+    pub refresh_if_stale: bool,
+    /// How long after being confirmed to be the latest revision the cached
+    /// revision is considered stale.
+    pub stale_after: Duration,
+    /// Where retrieved pages are cached.
+    pub cache_dir: PathBuf,
+    /// The URLs of the data sources.
+    pub urls: SourceUrls,
+    /// Options of the HTTP client.
+    pub http: HttpOptions,
+    /// Read the IANA registry from this file instead of the local cache.
+    pub iana_file: Option<PathBuf>,
+    /// The local services file, also used as a fallback.
+    pub services_file: PathBuf,
+}
+impl LoadOptions {
+    /// Resolve the options from a user configuration, using the defaults for
+    /// anything it does not set.
+    ///
+    /// Only the Wikipedia page is selected as a source.
+    /// Errors if the default cache directory cannot be determined.
+    pub fn from_config(config: &Config) -> color_eyre::Result<Self> {
+        let cache_dir = match &config.cache_dir {
+            Some(dir) => dir.clone(),
+            None => default_cache_dir()?,
+        };
+
+        let default_urls = SourceUrls::default();
+        let url_config = config.urls.clone();
+        let urls = SourceUrls {
+            history_api: url_config.history_api.unwrap_or(default_urls.history_api),
+            page: url_config.page.unwrap_or(default_urls.page),
+            origin_base: url_config.origin_base.unwrap_or(default_urls.origin_base),
+            iana_registry: url_config
+                .iana_registry
+                .unwrap_or(default_urls.iana_registry),
+        };
+
+        let default_http = HttpOptions::default();
+        let http_config = config.http.clone();
+        let http = HttpOptions {
+            timeout: http_config.timeout.map_or(default_http.timeout, Into::into),
+            retries: http_config.retries.unwrap_or(default_http.retries),
+            retry_delay: http_config
+                .retry_delay
+                .map_or(default_http.retry_delay, Into::into),
+            proxy: http_config.proxy,
+            ca_bundle: http_config.ca_bundle,
+        };
+
+        Ok(Self {
+            sources: vec![DataSourceKind::Wikipedia],
+            revision: None,
+            pull: config.pull == Some(PullPolicy::Always),
+            refresh_if_stale: config.pull == Some(PullPolicy::IfStale),
+            stale_after: config.stale_after.map_or(DEFAULT_STALE_AFTER, Into::into),
+            cache_dir,
+            urls,
+            http,
+            iana_file: None,
+            services_file: DEFAULT_SERVICES_PATH.into(),
+        })
+    }
+}
+
+/// The data sources loaded by [`Loader::load_sources`].
+#[derive(Debug)]
+pub struct LoadedSources {
+    /// The loaded databases, in the order of the selected sources.
+    pub dbs: Vec<PortDatabase>,
+    /// A retrieval of the latest revision running in the background, if the
+    /// cached revision is stale.
+    ///
+    /// It should be finished before exiting, to give it a chance to complete.
+    pub refresh: Option<BackgroundRefresh>,
+}
+
+/// Loads data sources according to some [`LoadOptions`].
+#[derive(Debug)]
+pub struct Loader {
+    options: LoadOptions,
+    /// Present if pulling, or if stale revisions are refreshed.
+    http_client: Option<HttpClient>,
+}
+impl Loader {
+    /// Create a loader, compressing pages cached by older versions.
+    ///
+    /// Errors if the HTTP options are invalid.
+    pub async fn new(options: LoadOptions) -> color_eyre::Result<Self> {
+        migrate_legacy_pages(&options.cache_dir).await;
+
+        // when not pulling, the client is only used to refresh stale revisions
+        let http_client = if options.pull || options.refresh_if_stale {
+            Some(HttpClient::new(&options.http).await?)
+        } else {
+            None
+        };
+        Ok(Self { options, http_client })
+    }
+
+    /// The options of this loader.
+    pub fn options(&self) -> &LoadOptions {
+        &self.options
+    }
+
+    /// The HTTP client, if sources are retrieved from the network.
+    pub fn client(&self) -> Option<&HttpClient> {
+        self.http_client.as_ref().filter(|_| self.options.pull)
+    }
+
+    /// Load a revision of the Wikipedia page, from the network if pulling.
+    ///
+    /// If a revision is absent, we use the latest revision. There is no
+    /// fallback to other sources.
+    pub async fn load_revision(&self, revision: Option<u64>) -> color_eyre::Result<PortDatabase> {
+        let LoadOptions { cache_dir, urls, .. } = &self.options;
+        let db = match self.client() {
+            Some(client) => get_database_online(cache_dir, client, urls, revision).await?,
+            None => get_database_offline(cache_dir, revision).await?,
+        };
+        Ok(self.with_urls(db))
+    }
+
+    /// Load all cached revisions of the Wikipedia page, or only the `last` few,
+    /// oldest first.
+    ///
+    /// If pulling, the latest revision is retrieved as well, or all of the
+    /// `last` few if specified.
+    pub async fn load_all_revisions(
+        &self,
+        last: Option<u32>,
+    ) -> color_eyre::Result<Vec<PortDatabase>> {
+        let LoadOptions { cache_dir, urls, .. } = &self.options;
+        let dbs = get_all_databases(cache_dir, self.client(), urls, last).await?;
+        Ok(dbs.into_iter().map(|db| self.with_urls(db)).collect())
+    }
+
+    /// Load the selected sources.
+    ///
+    /// If the latest revision of the Wikipedia page is used without pulling,
+    /// we check whether it is stale. If no revision is cached, the bundled
+    /// snapshot (if enabled) or the services file is used instead.
+    pub async fn load_sources(&self) -> color_eyre::Result<LoadedSources> {
+        let LoadOptions {
+            sources,
+            revision,
+            cache_dir,
+            urls,
+            iana_file,
+            services_file,
+            ..
+        } = &self.options;
+
+        let mut refresh = None;
+        let mut dbs: Vec<PortDatabase> = vec![];
+        for source in sources.iter().unique() {
+            let db = match (source, self.client()) {
+                (DataSourceKind::Wikipedia, Some(client)) => {
+                    get_database_online(cache_dir, client, urls, *revision).await?
+                }
+                (DataSourceKind::Wikipedia, None) => {
+                    match get_database_offline(cache_dir, *revision).await {
+                        // only check staleness if the user has not requested a revision
+                        Ok(db) if revision.is_none() => {
+                            let DataSource::Wikipedia { revision: cached } = db.source else {
+                                unreachable!("Wikipedia database has a different source");
+                            };
+                            refresh = self.check_staleness(cached).await?;
+                            db
+                        }
+                        Ok(db) => db,
+                        #[cfg(feature = "bundled-snapshot")]
+                        Err(err) if revision.is_none() => {
+                            warn!("{err:#}");
+                            warn!("Falling back to the bundled snapshot");
+                            get_snapshot_database()?
+                        }
+                        Err(err) if revision.is_none() && services_file.exists() => {
+                            warn!("{err:#}");
+                            warn!("Falling back to services file at {services_file:?}");
+                            get_services_database(services_file).await?
+                        }
+                        Err(err) => Err(err)?,
+                    }
+                }
+                (DataSourceKind::Iana, Some(client)) if iana_file.is_none() => {
+                    get_iana_database_online(cache_dir, client, urls).await?
+                }
+                (DataSourceKind::Iana, _) => {
+                    get_iana_database_offline(cache_dir, iana_file.as_deref()).await?
+                }
+                (DataSourceKind::Services, _) => get_services_database(services_file).await?,
+            };
+            // the fallback may have loaded a source twice
+            if !dbs.iter().any(|d| d.source == db.source) {
+                dbs.push(self.with_urls(db));
+            }
+        }
+
+        Ok(LoadedSources { dbs, refresh })
+    }
+
+    /// Build the service name index from some loaded databases.
+    ///
+    /// If none of them has service names, the services file is used instead.
+    pub async fn build_service_index(
+        &self,
+        dbs: &[PortDatabase],
+    ) -> color_eyre::Result<ServiceIndex> {
+        let services_file = &self.options.services_file;
+
+        let service_index = ServiceIndex::build(dbs);
+        if service_index.is_empty() && services_file.exists() {
+            let services_db = get_services_database(services_file).await?;
+            return Ok(ServiceIndex::build([&services_db]));
+        }
+        Ok(service_index)
+    }
+
+    /// Resolve links in a loaded database against the configured URLs.
+    fn with_urls(&self, mut db: PortDatabase) -> PortDatabase {
+        db.urls = self.options.urls.clone();
+        db
+    }
+
+    /// Check whether a cached revision is stale.
+    ///
+    /// If it is, the latest revision is retrieved in the background if enabled,
+    /// otherwise a warning is shown.
+    async fn check_staleness(
+        &self,
+        revision: u64,
+    ) -> color_eyre::Result<Option<BackgroundRefresh>> {
+        let LoadOptions { cache_dir, urls, stale_after, .. } = &self.options;
+
+        let checked = get_revision_check_time(cache_dir, revision).await?;
+        let age = SystemTime::now()
+            .duration_since(checked)
+            .unwrap_or_default();
+        if age <= *stale_after {
+            return Ok(None);
+        }
+
+        match &self.http_client {
+            Some(client) => {
+                warn!(
+                    "Using revision {revision} last checked {} ago; \
+                    retrieving the latest revision in the background for next time",
+                    format_age(age)
+                );
+                Ok(Some(BackgroundRefresh::spawn(
+                    cache_dir,
+                    client.clone(),
+                    urls.clone(),
+                )))
+            }
+            None => {
+                warn!(
+                    "Using revision {revision} last checked {} ago; \
+                    use `-p|--pull` to retrieve the latest revision",
+                    format_age(age)
+                );
+                Ok(None)
+            }
+        }
+    }
+}
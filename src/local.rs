@@ -0,0 +1,322 @@
+//! Listing the listening sockets of the local machine.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+};
+
+use color_eyre::eyre::{bail, OptionExt};
+use log::{debug, warn};
+use tokio::fs;
+
+use crate::query::SupportedProtocol;
+
+/// TCP socket state `TCP_LISTEN`, as shown in `/proc/net/tcp`.
+const TCP_LISTEN: u8 = 0x0A;
+/// Socket state `TCP_CLOSE`, which unconnected UDP sockets are in.
+const UDP_UNCONNECTED: u8 = 0x07;
+
+/// The process that owns a socket.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SocketOwner {
+    /// The process ID.
+    pub pid: u32,
+    /// The command name of the process, if it could be read.
+    pub command: Option<String>,
+}
+
+/// A socket on the local machine that is listening for connections or datagrams.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListeningSocket {
+    /// The transport protocol of the socket.
+    pub protocol: SupportedProtocol,
+    /// The local address the socket is bound to.
+    pub address: IpAddr,
+    /// The local port the socket is bound to.
+    pub port: u16,
+    /// The inode of the socket, used to find its owner.
+    pub inode: u64,
+    /// Only available if we have permission to inspect the owning process.
+    pub owner: Option<SocketOwner>,
+}
+
+/// List all listening TCP and UDP sockets by reading `/proc/net/{tcp,udp,tcp6,udp6}`.
+///
+/// `proc_root` is normally `/proc`. Missing tables (e.g. when IPv6 is disabled)
+/// are skipped.
+pub async fn list_listening_sockets(
+    proc_root: impl AsRef<Path>,
+) -> color_eyre::Result<Vec<ListeningSocket>> {
+    use SupportedProtocol as P;
+
+    let proc_root = proc_root.as_ref();
+
+    let mut sockets = vec![];
+    for (table, protocol) in [
+        ("tcp", P::Tcp),
+        ("tcp6", P::Tcp),
+        ("udp", P::Udp),
+        ("udp6", P::Udp),
+    ] {
+        let path = proc_root.join("net").join(table);
+        let content = match fs::read_to_string(&path).await {
+            Ok(c) => c,
+            Err(err) => {
+                debug!("Skipping {path:?}: {err}");
+                continue;
+            }
+        };
+        sockets.extend(parse_socket_table(&content, protocol));
+    }
+
+    let owners = find_socket_owners(proc_root).await;
+    for socket in sockets.iter_mut() {
+        socket.owner = owners.get(&socket.inode).cloned();
+    }
+
+    sockets.sort_by_key(|s| (s.port, s.protocol.to_string(), s.address));
+    sockets.dedup_by(|a, b| (a.protocol, a.address, a.port) == (b.protocol, b.address, b.port));
+    Ok(sockets)
+}
+
+/// Parse the listening sockets in a `/proc/net` socket table, e.g. `/proc/net/tcp`.
+///
+/// Malformed lines are skipped with a warning.
+fn parse_socket_table(content: &str, protocol: SupportedProtocol) -> Vec<ListeningSocket> {
+    // skip the header line
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| match parse_socket_line(line, protocol) {
+            Ok(socket) => socket,
+            Err(err) => {
+                warn!("Failed to parse a line of the {protocol} socket table: {err}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Parse a single line of a `/proc/net` socket table.
+///
+/// Returns `Ok(None)` if the socket is not listening.
+fn parse_socket_line(
+    line: &str,
+    protocol: SupportedProtocol,
+) -> color_eyre::Result<Option<ListeningSocket>> {
+    // sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout inode
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let [_sl, local, remote, state, _queue, _timer, _retransmit, _uid, _timeout, inode, ..] =
+        fields.as_slice()
+    else {
+        bail!("Too few fields: {line:?}");
+    };
+
+    let state = u8::from_str_radix(state, 16)?;
+    let listening = match protocol {
+        SupportedProtocol::Tcp => state == TCP_LISTEN,
+        SupportedProtocol::Udp => state == UDP_UNCONNECTED && parse_address(remote)?.1 == 0,
+        _ => false,
+    };
+    if !listening {
+        return Ok(None);
+    }
+
+    let (address, port) = parse_address(local)?;
+    let inode = inode.parse()?;
+    Ok(Some(ListeningSocket {
+        protocol,
+        address,
+        port,
+        inode,
+        owner: None,
+    }))
+}
+
+/// Parse an address in a `/proc/net` socket table, e.g. `0100007F:0016`.
+///
+/// Each 32-bit group of the address is printed in host byte order.
+fn parse_address(s: &str) -> color_eyre::Result<(IpAddr, u16)> {
+    let (addr_hex, port_hex) = s.split_once(':').ok_or_eyre("Address has no port")?;
+    let port = u16::from_str_radix(port_hex, 16)?;
+
+    let address = match addr_hex.len() {
+        8 => {
+            let bytes = u32::from_str_radix(addr_hex, 16)?.to_ne_bytes();
+            IpAddr::V4(Ipv4Addr::from(bytes))
+        }
+        32 => {
+            let mut bytes = [0u8; 16];
+            for (i, chunk) in bytes.chunks_exact_mut(4).enumerate() {
+                let group = u32::from_str_radix(&addr_hex[i * 8..(i + 1) * 8], 16)?;
+                chunk.copy_from_slice(&group.to_ne_bytes());
+            }
+            IpAddr::V6(Ipv6Addr::from(bytes))
+        }
+        _ => bail!("Unexpected address length: {addr_hex}"),
+    };
+    Ok((address, port))
+}
+
+/// Map socket inodes to their owning processes by scanning `/proc/<pid>/fd`.
+///
+/// Processes we have no permission to inspect are silently skipped.
+async fn find_socket_owners(proc_root: &Path) -> HashMap<u64, SocketOwner> {
+    let mut owners = HashMap::new();
+
+    let Ok(mut proc_dir) = fs::read_dir(proc_root).await else {
+        return owners;
+    };
+    while let Ok(Some(entry)) = proc_dir.next_entry().await {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u32>().ok())
+        else {
+            continue; // not a process
+        };
+        let Ok(mut fd_dir) = fs::read_dir(entry.path().join("fd")).await else {
+            continue; // no permission
+        };
+
+        let mut inodes = vec![];
+        while let Ok(Some(fd)) = fd_dir.next_entry().await {
+            let Ok(target) = fs::read_link(fd.path()).await else {
+                continue;
+            };
+            let inode = target
+                .to_str()
+                .and_then(|t| t.strip_prefix("socket:["))
+                .and_then(|t| t.strip_suffix(']'))
+                .and_then(|t| t.parse::<u64>().ok());
+            inodes.extend(inode);
+        }
+        if inodes.is_empty() {
+            continue;
+        }
+
+        let command = fs::read_to_string(entry.path().join("comm"))
+            .await
+            .ok()
+            .map(|c| c.trim().to_owned());
+        for inode in inodes {
+            let owner = SocketOwner { pid, command: command.clone() };
+            owners.entry(inode).or_insert(owner);
+        }
+    }
+
+    owners
+}
+
+// `/proc/net` tables print addresses in host byte order
+#[cfg(all(test, target_endian = "little"))]
+mod tests {
+    use super::*;
+
+    const TCP: &str = "\
+  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 0100007F:0277 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 1001 1 0000000000000000 100 0 0 10 0
+   1: 00000000:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 1002 1 0000000000000000 100 0 0 10 0
+   2: 0100007F:0277 0100007F:D2F0 01 00000000:00000000 00:00000000 00000000     0        0 1003 1 0000000000000000 20 4 30 10 -1
+   3: garbage
+";
+    const TCP6: &str = "\
+  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000000000000000000001000000:1F90 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 2001 1 0000000000000000 100 0 0 10 0
+   1: 0000000000000000FFFF00000100007F:0050 0000000000000000FFFF00000100007F:A1B2 06 00000000:00000000 03:00000000 00000000     0        0 0 3 0000000000000000
+";
+    const UDP: &str = "\
+   sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops
+  100: 00000000:0044 00000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 3001 2 0000000000000000 0
+  101: 0100007F:A000 0100007F:0035 01 00000000:00000000 00:00000000 00000000     0        0 3002 2 0000000000000000 0
+";
+
+    fn socket(
+        protocol: SupportedProtocol,
+        address: &str,
+        port: u16,
+        inode: u64,
+    ) -> ListeningSocket {
+        ListeningSocket {
+            protocol,
+            address: address.parse().unwrap(),
+            port,
+            inode,
+            owner: None,
+        }
+    }
+
+    #[test]
+    fn parses_listening_ipv4_sockets() {
+        let sockets = parse_socket_table(TCP, SupportedProtocol::Tcp);
+        assert_eq!(
+            sockets,
+            [
+                socket(SupportedProtocol::Tcp, "127.0.0.1", 631, 1001),
+                socket(SupportedProtocol::Tcp, "0.0.0.0", 22, 1002),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_listening_ipv6_sockets() {
+        let sockets = parse_socket_table(TCP6, SupportedProtocol::Tcp);
+        assert_eq!(sockets, [socket(SupportedProtocol::Tcp, "::1", 8080, 2001)]);
+    }
+
+    #[test]
+    fn parses_unconnected_udp_sockets() {
+        let sockets = parse_socket_table(UDP, SupportedProtocol::Udp);
+        assert_eq!(
+            sockets,
+            [socket(SupportedProtocol::Udp, "0.0.0.0", 68, 3001)]
+        );
+    }
+
+    #[test]
+    fn parses_ipv4_mapped_address() {
+        let (address, port) = parse_address("0000000000000000FFFF00000100007F:0050").unwrap();
+        assert_eq!(address, "::ffff:127.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(port, 80);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn maps_inodes_to_processes() {
+        let proc_root = tempfile::tempdir().unwrap();
+        let root = proc_root.path();
+
+        std::fs::create_dir_all(root.join("net")).unwrap();
+        std::fs::write(root.join("net/tcp"), TCP).unwrap();
+        std::fs::write(root.join("net/udp"), UDP).unwrap();
+
+        let fd_dir = root.join("42/fd");
+        std::fs::create_dir_all(&fd_dir).unwrap();
+        std::fs::write(root.join("42/comm"), "sshd\n").unwrap();
+        std::os::unix::fs::symlink("socket:[1002]", fd_dir.join("3")).unwrap();
+        std::os::unix::fs::symlink("/dev/null", fd_dir.join("4")).unwrap();
+
+        // a process whose command name cannot be read
+        let fd_dir = root.join("43/fd");
+        std::fs::create_dir_all(&fd_dir).unwrap();
+        std::os::unix::fs::symlink("socket:[3001]", fd_dir.join("5")).unwrap();
+
+        let sockets = list_listening_sockets(root).await.unwrap();
+        let owners = sockets
+            .iter()
+            .map(|s| {
+                (
+                    s.port,
+                    s.owner.as_ref().map(|o| (o.pid, o.command.as_deref())),
+                )
+            })
+            .collect::<Vec<_>>();
+        let expected = [
+            (22, Some((42, Some("sshd")))),
+            (68, Some((43, None))),
+            (631, None),
+        ];
+        assert_eq!(owners, expected);
+    }
+}
@@ -1,35 +1,70 @@
-use std::time::Duration;
+use std::io::Write;
 
 use clap::Parser;
-use color_eyre::eyre::{Context, OptionExt};
+use color_eyre::eyre::Context;
+use itertools::Itertools;
 use simplelog::{ColorChoice, TermLogger, TerminalMode};
-
-use crate::{
-    cli::{CliArgs, UserQuery},
-    consts::USER_AGENT,
-    display::Output,
-    parse::parse_page,
-    source::{get_wikipedia_page_offline, get_wikipedia_page_online},
+#[cfg(feature = "server")]
+use tokio::sync::RwLock;
+use tokio::{
+    fs,
+    io::{self, AsyncBufReadExt, AsyncReadExt, BufReader},
 };
 
-mod cli;
-mod consts;
-mod display;
-mod parse;
-mod source;
-mod store;
+#[cfg(feature = "repl")]
+use what_the_port::repl::Repl;
+#[cfg(feature = "server")]
+use what_the_port::server::{serve, ServerState};
+#[cfg(feature = "tui")]
+use what_the_port::tui::Browser;
+use what_the_port::{
+    cli::{CacheAction, CliArgs, Command},
+    config::{resolve_flag, Config, OutputFormat},
+    display::{CacheListOutput, CacheRemovalOutput, CacheVerifyOutput, Output},
+    load::{LoadOptions, Loader},
+    local::list_listening_sockets,
+    nmap::parse_nmap_xml,
+    query::{SupportedProtocol, UserQueryList},
+    source::{list_cache, prune_cache, remove_cached_revisions, verify_cache},
+    store::{FreePortQuery, PortDatabase, QueryFilter},
+};
 
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
 
     let CliArgs {
-        query,
+        command,
+        queries,
+        #[cfg(feature = "repl")]
+        interactive,
+        sources,
+        iana_file,
+        services_file,
+        port_types,
+        protocol,
+        categories,
+        history_api_url,
+        page_url,
+        origin_base_url,
+        iana_registry_url,
         revision,
         pull,
+        no_pull,
         show_links,
+        no_links,
         show_notes_and_references,
+        no_notes_and_references,
         json_output,
+        text_output,
+        stale_after,
+        timeout,
+        retries,
+        retry_delay,
+        proxy,
+        ca_bundle,
+        config: config_path,
+        cache_dir,
         verbosity,
     } = CliArgs::parse();
 
@@ -45,50 +80,295 @@ async fn main() -> color_eyre::Result<()> {
         ColorChoice::Auto,
     )?;
 
-    // get paths
-    let cache_dir = directories::ProjectDirs::from("org", "wtp", "what-the-port")
-        .ok_or_eyre("Cannot determine your home directory")?
-        .cache_dir()
-        .to_owned();
-
-    // get page
-    let (_page_path, page) = if pull {
-        let client = reqwest::ClientBuilder::new()
-            .user_agent(USER_AGENT)
-            .connection_verbose(true)
-            .timeout(Duration::from_secs(10))
-            .build()
-            .wrap_err("Failed to initialise HTTP client")?;
-        get_wikipedia_page_online(&cache_dir, &client, revision)
-            .await
-            .wrap_err("Failed to fetch Wikipedia page from network")?
-    } else {
-        get_wikipedia_page_offline(&cache_dir, revision)
-            .await
-            .wrap_err("Failed to fetch Wikipedia page from local cache")?
+    // load config
+    let config = match config_path {
+        Some(path) => Config::load(path, false).await?,
+        None => Config::load(Config::default_path()?, true).await?,
     };
 
-    // parse
-    let db = parse_page(&page).wrap_err("Failed to parse Wikipedia page")?;
+    // command line arguments take precedence over config
+    let show_links = resolve_flag(show_links, no_links, config.links);
+    let show_notes_and_references = resolve_flag(
+        show_notes_and_references,
+        no_notes_and_references,
+        config.references,
+    );
+    let json_output = resolve_flag(
+        json_output,
+        text_output,
+        config.output.map(|o| o == OutputFormat::Json),
+    );
+    let mut options = LoadOptions::from_config(&config)?;
+    options.sources = sources;
+    options.revision = revision;
+    options.pull = resolve_flag(pull, no_pull, Some(options.pull));
+    options.refresh_if_stale &= !no_pull;
+    options.iana_file = iana_file;
+    options.services_file = services_file;
+    if let Some(stale_after) = stale_after {
+        options.stale_after = stale_after.into();
+    }
+    if let Some(cache_dir) = cache_dir {
+        options.cache_dir = cache_dir;
+    }
+    let LoadOptions { urls, http, .. } = &mut options;
+    if let Some(url) = history_api_url {
+        urls.history_api = url;
+    }
+    if let Some(url) = page_url {
+        urls.page = url;
+    }
+    if let Some(url) = origin_base_url {
+        urls.origin_base = url;
+    }
+    if let Some(url) = iana_registry_url {
+        urls.iana_registry = url;
+    }
+    if let Some(timeout) = timeout {
+        http.timeout = timeout.into();
+    }
+    if let Some(retries) = retries {
+        http.retries = retries;
+    }
+    if let Some(retry_delay) = retry_delay {
+        http.retry_delay = retry_delay.into();
+    }
+    if proxy.is_some() {
+        http.proxy = proxy;
+    }
+    if ca_bundle.is_some() {
+        http.ca_bundle = ca_bundle;
+    }
+
+    // get databases
+    let loader = Loader::new(options).await?;
+    let mut refresh = None;
+    let mut dbs = vec![];
+    match command {
+        // only the compared revisions are needed
+        Some(Command::Diff { old, new }) => {
+            for revision in [old, new] {
+                dbs.push(loader.load_revision(Some(revision)).await?);
+            }
+        }
+        Some(Command::History { last, .. }) => dbs = loader.load_all_revisions(last).await?,
+        Some(Command::Cache { .. }) => {}
+        _ => {
+            let loaded = loader.load_sources().await?;
+            dbs = loaded.dbs;
+            refresh = loaded.refresh;
+        }
+    }
+    let service_index = loader.build_service_index(&dbs).await?;
+    let cache_dir = loader.options().cache_dir.clone();
 
     // set conditional colourisation
     yansi::whenever(yansi::Condition::TTY_AND_COLOR);
 
     // query and print
-    let output: Output = match query {
-        UserQuery::PortLookup(port) => db
-            .lookup(port, show_links, show_notes_and_references)
-            .into(),
-        UserQuery::Search(search) => db
-            .search(search, show_links, show_notes_and_references)
-            .into(),
+    let mut filter = QueryFilter { types: port_types, protocol, categories };
+    #[cfg(feature = "repl")]
+    if command.is_none() && interactive {
+        let repl = Repl {
+            dbs,
+            service_index,
+            filter,
+            show_links,
+            show_notes_and_references,
+            json_output,
+            loader,
+        };
+        repl.run().await?;
+        if let Some(refresh) = refresh {
+            refresh.finish().await;
+        }
+        return Ok(());
+    }
+    let outputs: Vec<Output> = match command {
+        Some(Command::Free {
+            range,
+            count,
+            block_size,
+            ignore_unofficial,
+            within_category,
+        }) => {
+            // a protocol specified in the range takes precedence
+            if range.protocol != SupportedProtocol::Any {
+                filter.protocol = range.protocol;
+            }
+            let query = FreePortQuery {
+                range: range.range,
+                count,
+                block_size,
+                ignore_unofficial,
+                within_category,
+            };
+            dbs.iter()
+                .map(|db| db.find_free(query.clone(), &filter).into())
+                .collect()
+        }
+        Some(Command::Local { proc_root }) => {
+            let sockets = list_listening_sockets(&proc_root)
+                .await
+                .wrap_err("Failed to list listening sockets")?;
+            let output = PortDatabase::lookup_sockets(
+                &dbs,
+                sockets,
+                &filter,
+                show_links,
+                show_notes_and_references,
+            );
+            vec![output.into()]
+        }
+        Some(Command::Nmap { file }) => {
+            let xml = if file.as_os_str() == "-" {
+                let mut xml = String::new();
+                io::stdin()
+                    .read_to_string(&mut xml)
+                    .await
+                    .wrap_err("Failed to read stdin")?;
+                xml
+            } else {
+                fs::read_to_string(&file)
+                    .await
+                    .wrap_err_with(|| format!("Failed to read {file:?}"))?
+            };
+            let hosts = parse_nmap_xml(&xml).wrap_err("Failed to parse nmap report")?;
+            let output = PortDatabase::lookup_nmap_report(
+                &dbs,
+                hosts,
+                &filter,
+                show_links,
+                show_notes_and_references,
+            );
+            vec![output.into()]
+        }
+        Some(Command::Diff { .. }) => {
+            let [old_db, new_db] = dbs.as_slice() else {
+                unreachable!("both revisions should have been loaded");
+            };
+            vec![old_db
+                .diff(new_db, &filter, show_links, show_notes_and_references)
+                .into()]
+        }
+        Some(Command::History { port, .. }) => {
+            vec![
+                PortDatabase::history(&dbs, port, &filter, show_links, show_notes_and_references)
+                    .into(),
+            ]
+        }
+        Some(Command::Cache { action }) => {
+            let output = match action {
+                CacheAction::List => CacheListOutput::from(list_cache(&cache_dir).await?).into(),
+                CacheAction::Prune { keep } => {
+                    CacheRemovalOutput::from(prune_cache(&cache_dir, keep).await?).into()
+                }
+                CacheAction::Rm { revisions } => {
+                    let removed = remove_cached_revisions(&cache_dir, &revisions).await?;
+                    CacheRemovalOutput::from(removed).into()
+                }
+                CacheAction::Verify { revisions } => {
+                    let results = verify_cache(&cache_dir, &revisions).await?;
+                    CacheVerifyOutput { results }.into()
+                }
+            };
+            vec![output]
+        }
+        #[cfg(feature = "tui")]
+        Some(Command::Browse { range }) => {
+            // a protocol specified in the range takes precedence
+            if let Some(range) = &range {
+                if range.protocol != SupportedProtocol::Any {
+                    filter.protocol = range.protocol;
+                }
+            }
+            let browser = Browser::new(&dbs, filter, range, show_links, show_notes_and_references);
+            tokio::task::block_in_place(|| browser.run())?;
+            if let Some(refresh) = refresh {
+                refresh.finish().await;
+            }
+            return Ok(());
+        }
+        #[cfg(feature = "server")]
+        Some(Command::Serve { listen, reload_interval }) => {
+            let state = ServerState {
+                dbs: RwLock::new(dbs),
+                service_index,
+                filter,
+                show_links,
+                show_notes_and_references,
+                loader,
+            };
+            // a requested revision should not be replaced
+            let reload_interval = revision.is_none().then_some(reload_interval.into());
+            serve(listen, state, reload_interval).await?;
+            if let Some(refresh) = refresh {
+                refresh.finish().await;
+            }
+            return Ok(());
+        }
+        Some(Command::Annotate) => {
+            let mut lines = BufReader::new(io::stdin()).lines();
+            while let Some(line) = lines.next_line().await.wrap_err("Failed to read stdin")? {
+                let annotated = PortDatabase::annotate_line(
+                    &dbs,
+                    line,
+                    &service_index,
+                    &filter,
+                    show_links,
+                    show_notes_and_references,
+                );
+                let output = Output::from(annotated);
+
+                // print as we go, so that the output can be streamed
+                let output_str = if json_output {
+                    serde_json::to_string(&output)?
+                } else {
+                    output.to_string()
+                };
+                let mut stdout = std::io::stdout().lock();
+                match writeln!(stdout, "{output_str}").and_then(|_| stdout.flush()) {
+                    Ok(()) => {}
+                    // downstream closed, e.g. piped to `head`
+                    Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => break,
+                    Err(err) => Err(err)?,
+                }
+            }
+            if let Some(refresh) = refresh {
+                refresh.finish().await;
+            }
+            return Ok(());
+        }
+        None => queries
+            .into_iter()
+            .flat_map(|UserQueryList(list)| list)
+            .cartesian_product(&dbs)
+            .map(|(query, db)| {
+                db.query(
+                    query,
+                    &service_index,
+                    &filter,
+                    show_links,
+                    show_notes_and_references,
+                )
+            })
+            .collect(),
     };
     let output_str = if json_output {
-        serde_json::to_string(&output)?
+        outputs
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?
+            .join("\n")
     } else {
-        output.to_string()
+        outputs.iter().map(Output::to_string).join("\n\n")
     };
     println!("{output_str}");
 
+    // the refresh is only for next time, so it is not waited on for long
+    if let Some(refresh) = refresh {
+        refresh.finish().await;
+    }
+
     Ok(())
 }
@@ -0,0 +1,175 @@
+//! Parsing nmap XML reports.
+
+use color_eyre::eyre::{bail, Context, OptionExt};
+use log::debug;
+
+use crate::query::SupportedProtocol;
+
+/// A scanned host in an nmap XML report.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NmapHost {
+    /// The address of the host.
+    pub address: String,
+    /// The first hostname of the host, if any.
+    pub hostname: Option<String>,
+    /// The open ports of the host.
+    pub ports: Vec<NmapPort>,
+}
+
+/// An open port of a scanned host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NmapPort {
+    /// The transport protocol of the port.
+    pub protocol: SupportedProtocol,
+    /// The port number.
+    pub number: u16,
+    /// The service name guessed by nmap, e.g. `ssh`.
+    pub service: Option<String>,
+}
+
+/// Parse a report produced by `nmap -oX`.
+///
+/// Only ports in the `open` state are kept. Hosts without open ports are kept
+/// so that they still show up in the output.
+pub fn parse_nmap_xml(xml: &str) -> color_eyre::Result<Vec<NmapHost>> {
+    // nmap reports always start with `<!DOCTYPE nmaprun>`
+    let options = roxmltree::ParsingOptions { allow_dtd: true, ..Default::default() };
+    let doc = roxmltree::Document::parse_with_options(xml, options).wrap_err("Invalid XML")?;
+    let root = doc.root_element();
+    if !root.has_tag_name("nmaprun") {
+        bail!(
+            r#"Expected root element "nmaprun", found "{}""#,
+            root.tag_name().name()
+        );
+    }
+
+    root.children()
+        .filter(|node| node.has_tag_name("host"))
+        .map(|host| {
+            // prefer IP addresses over MAC addresses
+            let address = host
+                .children()
+                .filter(|node| node.has_tag_name("address"))
+                .min_by_key(|node| node.attribute("addrtype") == Some("mac"))
+                .and_then(|node| node.attribute("addr"))
+                .ok_or_eyre("Host has no address")?
+                .to_owned();
+            let hostname = host
+                .descendants()
+                .find(|node| node.has_tag_name("hostname"))
+                .and_then(|node| node.attribute("name"))
+                .map(Into::into);
+
+            let ports = host
+                .descendants()
+                .filter(|node| node.has_tag_name("port"))
+                .filter(|port| {
+                    port.children()
+                        .find(|node| node.has_tag_name("state"))
+                        .and_then(|node| node.attribute("state"))
+                        == Some("open")
+                })
+                .filter_map(|port| {
+                    let protocol_str = port.attribute("protocol").unwrap_or_default();
+                    let Ok(protocol) = protocol_str.parse() else {
+                        // e.g. `ip` in protocol scans
+                        debug!(r#"Ignoring unsupported protocol "{protocol_str}" of {address}"#);
+                        return None;
+                    };
+                    Some((port, protocol))
+                })
+                .map(|(port, protocol)| {
+                    let number = port
+                        .attribute("portid")
+                        .ok_or_eyre("Port has no number")?
+                        .parse()
+                        .wrap_err("Invalid port number")?;
+                    let service = port
+                        .children()
+                        .find(|node| node.has_tag_name("service"))
+                        .and_then(|node| node.attribute("name"))
+                        .map(Into::into);
+                    Ok(NmapPort { protocol, number, service })
+                })
+                .collect::<color_eyre::Result<_>>()?;
+
+            Ok(NmapHost { address, hostname, ports })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE nmaprun>
+<nmaprun scanner="nmap" args="nmap -oX - 192.168.1.0/30" version="7.95">
+<host>
+  <status state="up" reason="arp-response"/>
+  <address addr="00:11:22:33:44:55" addrtype="mac"/>
+  <address addr="192.168.1.1" addrtype="ipv4"/>
+  <hostnames><hostname name="router.lan" type="PTR"/></hostnames>
+  <ports>
+    <extraports state="closed" count="996"/>
+    <port protocol="tcp" portid="22"><state state="open" reason="syn-ack"/><service name="ssh" method="probed"/></port>
+    <port protocol="tcp" portid="80"><state state="open" reason="syn-ack"/><service name="http" method="table"/></port>
+    <port protocol="tcp" portid="81"><state state="closed" reason="reset"/><service name="hosts2-ns" method="table"/></port>
+    <port protocol="udp" portid="53"><state state="filtered" reason="no-response"/></port>
+  </ports>
+</host>
+<host>
+  <status state="up" reason="echo-reply"/>
+  <address addr="192.168.1.2" addrtype="ipv4"/>
+  <ports>
+    <port protocol="udp" portid="5353"><state state="open" reason="udp-response"/></port>
+  </ports>
+</host>
+</nmaprun>
+"#;
+
+    #[test]
+    fn parses_open_ports_of_every_host() {
+        let hosts = parse_nmap_xml(REPORT).unwrap();
+
+        let expected = [
+            NmapHost {
+                address: "192.168.1.1".into(),
+                hostname: Some("router.lan".into()),
+                ports: vec![
+                    NmapPort {
+                        protocol: SupportedProtocol::Tcp,
+                        number: 22,
+                        service: Some("ssh".into()),
+                    },
+                    NmapPort {
+                        protocol: SupportedProtocol::Tcp,
+                        number: 80,
+                        service: Some("http".into()),
+                    },
+                ],
+            },
+            NmapHost {
+                address: "192.168.1.2".into(),
+                hostname: None,
+                ports: vec![NmapPort {
+                    protocol: SupportedProtocol::Udp,
+                    number: 5353,
+                    service: None,
+                }],
+            },
+        ];
+        assert_eq!(hosts, expected);
+    }
+
+    #[test]
+    fn invalid_xml_is_error() {
+        assert!(parse_nmap_xml("<nmaprun><host>").is_err());
+        assert!(parse_nmap_xml("not xml at all").is_err());
+    }
+
+    #[test]
+    fn wrong_root_element_is_error() {
+        assert!(parse_nmap_xml("<html></html>").is_err());
+    }
+}
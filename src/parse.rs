@@ -1,22 +1,24 @@
+//! Parsing the Wikipedia page into a port database.
+
 use std::{
     iter,
     ops::{Deref, RangeInclusive},
     sync::{Arc, LazyLock},
 };
 
-use color_eyre::eyre::{bail, OptionExt};
+use color_eyre::eyre::{bail, eyre, OptionExt};
 use ego_tree::NodeRef;
 use itertools::Itertools;
 use log::{error, info, trace, warn};
 use regex::Regex;
 use scraper::{node::Element, CaseSensitivity, ElementRef, Html, Node, Selector};
-use serde::Serialize;
-use serde_with::{serde_as, DisplayFromStr};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_with::{serde_as, DeserializeAs, SerializeAs};
 
-use crate::store::{PortDatabase, PortRangeInfo, PortType};
+use crate::store::{DataSource, PortDatabase, PortRangeInfo, PortType};
 
 /// Parse the Wikipedia port list page from its HTML source.
-pub fn parse_page(html_str: &str) -> color_eyre::Result<PortDatabase> {
+pub fn parse_page(html_str: &str, revision: u64) -> color_eyre::Result<PortDatabase> {
     let document = Html::parse_document(html_str);
 
     let table_selector = Selector::parse(".wikitable.sortable").unwrap();
@@ -29,7 +31,11 @@ pub fn parse_page(html_str: &str) -> color_eyre::Result<PortDatabase> {
         .flatten()
         .collect_vec();
 
-    Ok(PortDatabase(list))
+    Ok(PortDatabase {
+        source: DataSource::Wikipedia { revision },
+        ports: list,
+        urls: Default::default(),
+    })
 }
 
 static CELL_SELECTOR: LazyLock<Selector> = LazyLock::new(|| Selector::parse("td").unwrap());
@@ -221,6 +227,8 @@ where
         sctp_type: port_types[2],
         dccp_type: port_types[3],
         rich_description,
+        service_names: vec![],
+        registration: None,
     })
 }
 
@@ -228,39 +236,85 @@ where
 ///
 /// A cell may contain multiple different kinds concatenated together.
 #[serde_as]
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum RichTextSpan {
     /// Plain text segment.
-    Text { text: String },
+    Text {
+        /// The text.
+        text: String,
+    },
     /// Abbreviation.
-    Abbreviation { short: String, long: Option<String> },
+    Abbreviation {
+        /// The abbreviated text.
+        short: String,
+        /// The full text, if given.
+        long: Option<String>,
+    },
     /// Code block.
-    Code { text: String },
+    Code {
+        /// The code.
+        text: String,
+    },
     /// A link to somewhere within the same origin.
-    SiteLink { text: String, link: String },
+    SiteLink {
+        /// The displayed text.
+        text: String,
+        /// The link target, relative to the origin.
+        link: String,
+    },
     /// A link to somewhere within the same origin that does not yet exist.
-    SiteLinkNonExistent { text: String, link: String },
+    SiteLinkNonExistent {
+        /// The displayed text.
+        text: String,
+        /// The link target, relative to the origin.
+        link: String,
+    },
     /// A link to somewhere external.
-    ExternalLink { text: String, link: String },
+    ExternalLink {
+        /// The displayed text.
+        text: String,
+        /// The link target.
+        link: String,
+    },
     /// A link to a note in superscript, e.g. `[note 1]`.
     ///
     /// Always an ID on the same page.
-    Note { number: usize, note_id: String },
+    Note {
+        /// The displayed number of the note.
+        number: usize,
+        /// The ID of the note on the page.
+        note_id: String,
+    },
     /// A link to a reference in superscript, e.g. `[69]`.
     ///
     /// Always an ID on the same page.
-    Reference { number: usize, ref_id: String },
+    Reference {
+        /// The displayed number of the reference.
+        number: usize,
+        /// The ID of the reference on the page.
+        ref_id: String,
+    },
     /// A link to an annotation in superscript, e.g. `[jargon]`.
     ///
     /// Always a site link.
-    Annotation { text: String, link: String },
+    Annotation {
+        /// The displayed text.
+        text: String,
+        /// The link target, relative to the origin.
+        link: String,
+    },
     /// A subscript.
-    Subscript { text: String },
+    Subscript {
+        /// The text.
+        text: String,
+    },
     /// A span of unknown type.
     Unknown {
+        /// The text content of the span.
         text: String,
-        #[serde_as(as = "DisplayFromStr")]
+        /// Why the span could not be parsed.
+        #[serde_as(as = "ReportAsString")]
         // we use `Arc` here so that we can `#[derive(Clone)]`
         err: Arc<color_eyre::Report>,
     },
@@ -443,8 +497,31 @@ impl RichTextSpan {
     ) -> bool {
         let search = search.as_ref().to_lowercase();
 
-        // eligible search scope
-        let search_scope = match self {
+        // matches if found anywhere in search scope
+        self.search_scope(include_links, include_notes_and_references)
+            .into_iter()
+            .any(|t| t.to_lowercase().contains(&search))
+    }
+
+    /// Check if this span matches the regex.
+    pub fn matches_regex(
+        &self,
+        regex: &Regex,
+        include_links: bool,
+        include_notes_and_references: bool,
+    ) -> bool {
+        self.search_scope(include_links, include_notes_and_references)
+            .into_iter()
+            .any(|t| regex.is_match(t))
+    }
+
+    /// The strings of this span eligible for searching.
+    fn search_scope(
+        &self,
+        include_links: bool,
+        include_notes_and_references: bool,
+    ) -> Vec<&String> {
+        match self {
             Self::Text { text } | Self::Code { text } | Self::Subscript { text } => vec![text],
             Self::Abbreviation { short, long } => iter::once(short).chain(long.as_ref()).collect(),
             Self::SiteLink { text, link }
@@ -463,15 +540,33 @@ impl RichTextSpan {
             }
             Self::Annotation { .. } => vec![], // annotations are not helpful
             Self::Unknown { text, .. } => vec![text],
-        };
+        }
+    }
+}
 
-        // matches if found anywhere in search scope
-        search_scope
-            .into_iter()
-            .any(|t| t.to_lowercase().contains(&search))
+/// Helper for (de)serialising an error report as its display string.
+///
+/// The deserialised report only retains the message.
+struct ReportAsString;
+impl SerializeAs<Arc<color_eyre::Report>> for ReportAsString {
+    fn serialize_as<S>(source: &Arc<color_eyre::Report>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(source)
+    }
+}
+impl<'de> DeserializeAs<'de, Arc<color_eyre::Report>> for ReportAsString {
+    fn deserialize_as<D>(deserializer: D) -> Result<Arc<color_eyre::Report>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let msg = String::deserialize(deserializer)?;
+        Ok(Arc::new(eyre!(msg)))
     }
 }
 
+/// Parse the content of a `td` element into rich text spans.
 pub fn parse_rich_text_cell(cell: ElementRef<'_>) -> color_eyre::Result<Vec<RichTextSpan>> {
     // sanity check
     if cell.value().name() != "td" {
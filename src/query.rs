@@ -0,0 +1,190 @@
+//! User queries, and the port selections they are made of.
+
+use std::{fmt, ops::RangeInclusive, str::FromStr};
+
+use serde_with::SerializeDisplay;
+
+use crate::search::SearchQuery;
+
+/// A single query of the user.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UserQuery {
+    /// User specified a port lookup.
+    PortLookup(PortSelection),
+    /// User specified a port range lookup.
+    RangeLookup(PortRangeSelection),
+    /// User specified a search term.
+    ///
+    /// If the term is the exact name of a known service, the service's ports
+    /// are looked up instead.
+    Search(SearchQuery),
+}
+impl fmt::Display for UserQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PortLookup(port) => write!(f, "{port}"),
+            Self::RangeLookup(range) => write!(f, "{range}"),
+            Self::Search(s) => write!(f, r#"Search: "{s}""#),
+        }
+    }
+}
+impl FromStr for UserQuery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let query = if let Ok(port) = s.parse() {
+            Self::PortLookup(port)
+        } else if let Ok(range) = s.parse() {
+            Self::RangeLookup(range)
+        } else {
+            Self::Search(s.parse()?)
+        };
+        Ok(query)
+    }
+}
+
+/// One or more user queries specified in a single argument.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserQueryList(pub Vec<UserQuery>);
+impl FromStr for UserQueryList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // only split on commas if every item is a port specification,
+        // otherwise treat the entire argument as a single query
+        // so that search terms can still contain commas
+        let ports = s
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                if let Ok(port) = item.parse() {
+                    Some(UserQuery::PortLookup(port))
+                } else if let Ok(range) = item.parse() {
+                    Some(UserQuery::RangeLookup(range))
+                } else {
+                    None
+                }
+            })
+            .collect::<Option<Vec<_>>>();
+
+        let list = match ports {
+            Some(ports) if !ports.is_empty() => ports,
+            _ => vec![s.parse()?],
+        };
+        Ok(Self(list))
+    }
+}
+
+/// A port, optionally of a specific protocol, e.g. `443/udp`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, SerializeDisplay)]
+pub struct PortSelection {
+    /// The port number.
+    pub number: u16,
+    /// The protocol, or `Any`.
+    pub protocol: SupportedProtocol,
+}
+impl fmt::Display for PortSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let PortSelection { number, protocol } = self;
+        match protocol {
+            SupportedProtocol::Any => write!(f, "{number}"),
+            proto => write!(f, "{number}/{proto}"),
+        }
+    }
+}
+impl FromStr for PortSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (number_str, protocol) = match s.split_once('/') {
+            Some((n, p)) => {
+                let proto = p
+                    .parse()
+                    .map_err(|_| format!(r#"Unknown protocol: "{p}""#))?;
+                (n, proto)
+            }
+            None => (s, SupportedProtocol::Any),
+        };
+        let number = number_str
+            .parse()
+            .map_err(|_| format!(r#""{number_str}" is not a valid port number"#))?;
+        Ok(Self { number, protocol })
+    }
+}
+
+/// A range of ports, optionally of a specific protocol, e.g. `8000-8100/tcp`.
+#[derive(Clone, Debug, PartialEq, Eq, SerializeDisplay)]
+pub struct PortRangeSelection {
+    /// The ports in the range, inclusive.
+    pub range: RangeInclusive<u16>,
+    /// The protocol, or `Any`.
+    pub protocol: SupportedProtocol,
+}
+impl fmt::Display for PortRangeSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let PortRangeSelection { range, protocol } = self;
+        let (start, end) = (range.start(), range.end());
+        match protocol {
+            SupportedProtocol::Any => write!(f, "{start}-{end}"),
+            proto => write!(f, "{start}-{end}/{proto}"),
+        }
+    }
+}
+impl FromStr for PortRangeSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range_str, protocol) = match s.split_once('/') {
+            Some((r, p)) => {
+                let proto = p
+                    .parse()
+                    .map_err(|_| format!(r#"Unknown protocol: "{p}""#))?;
+                (r, proto)
+            }
+            None => (s, SupportedProtocol::Any),
+        };
+        let (start_str, end_str) = range_str
+            .split_once('-')
+            .ok_or_else(|| format!(r#""{range_str}" is not a port range"#))?;
+        let start: u16 = start_str
+            .parse()
+            .map_err(|_| format!(r#""{start_str}" is not a valid port number"#))?;
+        let end: u16 = end_str
+            .parse()
+            .map_err(|_| format!(r#""{end_str}" is not a valid port number"#))?;
+        if start > end {
+            return Err(format!(r#"Port range "{range_str}" is reversed"#));
+        }
+        Ok(Self { range: start..=end, protocol })
+    }
+}
+
+/// Known port protocols.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum SupportedProtocol {
+    /// Protocol unspecified.
+    #[default]
+    Any,
+    /// Transmission Control Protocol.
+    Tcp,
+    /// User Datagram Protocol.
+    Udp,
+    /// Stream Control Transmission Protocol.
+    Sctp,
+    /// Datagram Congestion Control Protocol.
+    Dccp,
+}
+
+/// Known data sources.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum DataSourceKind {
+    /// The Wikipedia page "List of TCP and UDP port numbers".
+    Wikipedia,
+    /// The IANA Service Name and Transport Protocol Port Number Registry.
+    Iana,
+    /// The local services file, typically `/etc/services`.
+    Services,
+}
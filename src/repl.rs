@@ -0,0 +1,304 @@
+//! The interactive prompt, which keeps the databases loaded between queries.
+
+use color_eyre::eyre::{bail, Context};
+use log::warn;
+use rustyline::{error::ReadlineError, history::History, DefaultEditor};
+
+use crate::{
+    display::Output,
+    load::Loader,
+    query::UserQueryList,
+    source::list_cache,
+    store::{DataSource, PortDatabase, QueryFilter, ServiceIndex},
+};
+
+const HELP: &str = "\
+Enter one or more queries separated by spaces, like on the command line,
+e.g. `22 443/udp 8000-8100 ssh`. Quote a search to keep its words together,
+e.g. `\"secure shell\"` or `'kafka AND -zookeeper'`.
+
+Commands:
+    :links [on|off]        Toggle or set the link section
+    :refs [on|off]         Toggle or set notes and references
+    :rev [REVISION|latest] Show the loaded revision, or switch to another one
+    :history [N]           Show the last N entered lines (default 20)
+    :help                  Show this help
+    :quit                  Exit (or press Ctrl-D)";
+
+/// An interactive session.
+#[derive(Debug)]
+pub struct Repl {
+    /// The loaded databases, in the order of the selected sources.
+    pub dbs: Vec<PortDatabase>,
+    /// Used to resolve service names.
+    pub service_index: ServiceIndex,
+    /// Which use cases are shown.
+    pub filter: QueryFilter,
+    /// Whether links are shown, toggled by `:links`.
+    pub show_links: bool,
+    /// Whether notes and references are shown, toggled by `:refs`.
+    pub show_notes_and_references: bool,
+    /// Whether results are printed as JSON.
+    pub json_output: bool,
+    /// Loads other revisions, from the network if pulling.
+    ///
+    /// The line history is kept in its cache directory.
+    pub loader: Loader,
+}
+impl Repl {
+    /// Run the prompt until the user exits.
+    ///
+    /// Line history is persisted in the cache directory.
+    pub async fn run(mut self) -> color_eyre::Result<()> {
+        let cache_dir = self.loader.options().cache_dir.clone();
+        let history_path = cache_dir.join("repl-history.txt");
+        let mut editor = DefaultEditor::new().wrap_err("Failed to initialise line editor")?;
+        if history_path.exists() {
+            if let Err(err) = editor.load_history(&history_path) {
+                warn!("Failed to load history from {history_path:?}: {err}");
+            }
+        }
+
+        let sources = self.dbs.iter().map(|db| db.source.to_string());
+        println!("Loaded {}", itertools::join(sources, ", "));
+        println!("Type `:help` for commands, `:quit` or Ctrl-D to exit");
+
+        loop {
+            // line editing is blocking
+            let line = match tokio::task::block_in_place(|| editor.readline("wtp> ")) {
+                Ok(line) => line,
+                // discard the current line, like a shell
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(err) => Err(err).wrap_err("Failed to read line")?,
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            editor.add_history_entry(line)?;
+
+            if matches!(line, ":q" | ":quit" | ":exit") {
+                break;
+            }
+            let res = match line.strip_prefix(':') {
+                Some(command) => self.run_command(command, &editor).await,
+                None => self.run_query(line),
+            };
+            if let Err(err) = res {
+                eprintln!("Error: {err:#}");
+            }
+        }
+
+        tokio::fs::create_dir_all(&cache_dir).await?;
+        if let Err(err) = editor.save_history(&history_path) {
+            warn!("Failed to save history to {history_path:?}: {err}");
+        }
+        Ok(())
+    }
+
+    /// Answer the queries in a line with every loaded database.
+    ///
+    /// Like on the command line, each word of the line is a separate query.
+    fn run_query(&self, line: &str) -> color_eyre::Result<()> {
+        let mut queries = vec![];
+        for word in split_line(line)? {
+            match word.parse::<UserQueryList>() {
+                Ok(UserQueryList(list)) => queries.extend(list),
+                Err(err) => bail!(err),
+            }
+        }
+        for query in queries {
+            for db in &self.dbs {
+                let output = db.query(
+                    query.clone(),
+                    &self.service_index,
+                    &self.filter,
+                    self.show_links,
+                    self.show_notes_and_references,
+                );
+                self.print(&output)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run a command, without the leading colon.
+    async fn run_command(
+        &mut self,
+        command: &str,
+        editor: &DefaultEditor,
+    ) -> color_eyre::Result<()> {
+        let (name, arg) = match command.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, Some(arg.trim())),
+            None => (command, None),
+        };
+        match name {
+            "links" => {
+                self.show_links = parse_toggle(arg, self.show_links)?;
+                println!("Links: {}", on_off(self.show_links));
+            }
+            "refs" | "references" => {
+                self.show_notes_and_references = parse_toggle(arg, self.show_notes_and_references)?;
+                println!(
+                    "Notes and references: {}",
+                    on_off(self.show_notes_and_references)
+                );
+            }
+            "rev" | "revision" => match arg {
+                None => self.show_revisions().await?,
+                Some("latest") => self.switch_revision(None).await?,
+                Some(rev) => {
+                    let rev = rev
+                        .parse()
+                        .wrap_err_with(|| format!(r#"Invalid revision "{rev}""#))?;
+                    self.switch_revision(Some(rev)).await?
+                }
+            },
+            "history" => {
+                let count = match arg {
+                    Some(n) => n
+                        .parse()
+                        .wrap_err_with(|| format!(r#"Invalid count "{n}""#))?,
+                    None => 20,
+                };
+                let history = editor.history();
+                // the current line is excluded
+                let skip = history.len().saturating_sub(count + 1);
+                for (i, line) in history.iter().enumerate().skip(skip).take(count) {
+                    println!("{:>5}  {line}", i + 1);
+                }
+            }
+            "help" => println!("{HELP}"),
+            _ => bail!(r#"Unknown command ":{name}"; type `:help` for commands"#),
+        }
+        Ok(())
+    }
+
+    /// Show the loaded revision of the Wikipedia page, and the cached ones.
+    async fn show_revisions(&self) -> color_eyre::Result<()> {
+        match self.wikipedia_db_index() {
+            Some(idx) => println!("Using {}", self.dbs[idx].source),
+            None => println!("Wikipedia is not a loaded source"),
+        }
+        let cached = list_cache(&self.loader.options().cache_dir).await?;
+        if !cached.is_empty() {
+            let revisions = cached.iter().map(|c| c.revision);
+            println!("Cached revisions: {}", itertools::join(revisions, ", "));
+        }
+        Ok(())
+    }
+
+    /// Replace the loaded revision of the Wikipedia page.
+    ///
+    /// If a revision is absent, we use the latest revision.
+    async fn switch_revision(&mut self, revision: Option<u64>) -> color_eyre::Result<()> {
+        let Some(idx) = self.wikipedia_db_index() else {
+            bail!("Wikipedia is not a loaded source; use `--source wikipedia`");
+        };
+        let db = self.loader.load_revision(revision).await?;
+        println!("Switched to {}", db.source);
+        self.dbs[idx] = db;
+        Ok(())
+    }
+
+    /// Find the loaded database of the Wikipedia page, if any.
+    fn wikipedia_db_index(&self) -> Option<usize> {
+        self.dbs.iter().position(|db| match db.source {
+            DataSource::Wikipedia { .. } => true,
+            #[cfg(feature = "bundled-snapshot")]
+            DataSource::BundledSnapshot { .. } => true,
+            DataSource::Iana | DataSource::Services { .. } => false,
+        })
+    }
+
+    fn print(&self, output: &Output) -> color_eyre::Result<()> {
+        if self.json_output {
+            println!("{}", serde_json::to_string(output)?);
+        } else {
+            println!("{output}\n");
+        }
+        Ok(())
+    }
+}
+
+/// Split a line into words on whitespace, like a shell.
+///
+/// Text in single or double quotes is kept together, without the quotes.
+fn split_line(line: &str) -> color_eyre::Result<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some(next) if next == c => break,
+                        Some(next) => word.push(next),
+                        None => bail!("Unclosed quote"),
+                    }
+                }
+            }
+            c if c.is_whitespace() => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Parse the argument of a toggle command.
+///
+/// If the argument is absent, the current state is flipped.
+fn parse_toggle(arg: Option<&str>, current: bool) -> color_eyre::Result<bool> {
+    match arg {
+        None => Ok(!current),
+        Some("on") => Ok(true),
+        Some("off") => Ok(false),
+        Some(arg) => bail!(r#"Expected "on" or "off", found "{arg}""#),
+    }
+}
+
+fn on_off(state: bool) -> &'static str {
+    if state {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_on_whitespace() {
+        let words = split_line("22  443/udp\t ssh").unwrap();
+        assert_eq!(words, ["22", "443/udp", "ssh"]);
+    }
+
+    #[test]
+    fn keeps_quoted_text_together() {
+        let words = split_line(r#"22 "secure shell" 'kafka AND -zookeeper'"#).unwrap();
+        assert_eq!(words, ["22", "secure shell", "kafka AND -zookeeper"]);
+    }
+
+    #[test]
+    fn keeps_other_quotes_within_quotes() {
+        let words = split_line(r#"'"secure shell" OR ssh' x"y'z"w"#).unwrap();
+        assert_eq!(words, [r#""secure shell" OR ssh"#, "xy'zw"]);
+    }
+
+    #[test]
+    fn empty_quotes_are_a_word() {
+        let words = split_line(r#"22 """#).unwrap();
+        assert_eq!(words, ["22", ""]);
+    }
+
+    #[test]
+    fn unclosed_quote_is_error() {
+        assert!(split_line(r#"22 "secure shell"#).is_err());
+    }
+}
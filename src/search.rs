@@ -0,0 +1,324 @@
+//! Search query syntax.
+
+use std::{fmt, str::FromStr};
+
+use regex::{Regex, RegexBuilder};
+
+/// A parsed search query.
+///
+/// All matching is case-insensitive.
+#[derive(Clone, Debug)]
+pub struct SearchQuery {
+    /// The query as entered by the user.
+    raw: String,
+    kind: SearchKind,
+}
+
+#[derive(Clone, Debug)]
+enum SearchKind {
+    /// A regular expression, e.g. `/postgres(ql)?/`.
+    Regex(Regex),
+    /// Terms combined with `AND`, `OR` and `-`, e.g. `kafka AND -zookeeper`.
+    ///
+    /// Terms without an operator in between are combined with `AND`,
+    /// so a plain word or phrase is a boolean search too.
+    ///
+    /// Stored as alternatives of conjunctions, since `AND` binds tighter than `OR`.
+    Boolean(Vec<Vec<SearchTerm>>),
+}
+
+/// A single term of a boolean search.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchTerm {
+    /// The lowercase phrase to search for.
+    pub phrase: String,
+    /// Whether the phrase must be absent instead.
+    pub negated: bool,
+}
+
+/// A token of a boolean search.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Term(SearchTerm),
+    Operator(Operator),
+}
+
+/// An operator of a boolean search.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "UPPERCASE")]
+enum Operator {
+    And,
+    Or,
+}
+
+impl SearchQuery {
+    /// The query as entered by the user.
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    /// Check if a text matches this query.
+    ///
+    /// `matches_phrase` checks if the text contains a lowercase phrase, and
+    /// `matches_regex` checks if the text matches a regex.
+    pub fn matches(
+        &self,
+        matches_phrase: impl Fn(&str) -> bool,
+        matches_regex: impl Fn(&Regex) -> bool,
+    ) -> bool {
+        match &self.kind {
+            SearchKind::Regex(regex) => matches_regex(regex),
+            SearchKind::Boolean(alternatives) => alternatives.iter().any(|terms| {
+                terms
+                    .iter()
+                    .all(|term| matches_phrase(&term.phrase) != term.negated)
+            }),
+        }
+    }
+}
+impl fmt::Display for SearchQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.raw)
+    }
+}
+impl PartialEq for SearchQuery {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw == other.raw
+    }
+}
+impl Eq for SearchQuery {}
+impl FromStr for SearchQuery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw = s.to_owned();
+        let trimmed = s.trim();
+
+        // regex
+        if let Some(pattern) = trimmed
+            .strip_prefix('/')
+            .and_then(|rest| rest.strip_suffix('/'))
+        {
+            if pattern.is_empty() {
+                return Err("Regex is empty".into());
+            }
+            let regex = RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .map_err(|err| format!("Invalid regex: {err}"))?;
+            return Ok(Self { raw, kind: SearchKind::Regex(regex) });
+        }
+
+        let tokens = tokenize(trimmed)?;
+        if tokens.is_empty() {
+            return Err("Search is empty".into());
+        }
+        // operators must be between two terms
+        for (idx, token) in tokens.iter().enumerate() {
+            let Token::Operator(op) = token else {
+                continue;
+            };
+            let before = idx.checked_sub(1).map(|idx| &tokens[idx]);
+            let after = tokens.get(idx + 1);
+            if !matches!(before, Some(Token::Term(_))) || !matches!(after, Some(Token::Term(_))) {
+                return Err(format!(r#"Expected a search term on both sides of "{op}""#));
+            }
+        }
+
+        let alternatives = tokens
+            .split(|token| *token == Token::Operator(Operator::Or))
+            .map(|group| {
+                group
+                    .iter()
+                    .filter_map(|token| match token {
+                        Token::Term(term) => Some(term.clone()),
+                        Token::Operator(_) => None,
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(Self {
+            raw,
+            kind: SearchKind::Boolean(alternatives),
+        })
+    }
+}
+
+/// Split a boolean search into terms and operators.
+///
+/// Terms are either single words or quoted phrases, optionally prefixed with
+/// `-` for negation. Only unquoted uppercase `AND` and `OR` are operators.
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut negated = false;
+        if c == '-' {
+            chars.next();
+            match chars.peek() {
+                Some(next) if !next.is_whitespace() => negated = true,
+                // a lone dash is just a word
+                _ => {
+                    tokens.push(Token::Term(SearchTerm { phrase: "-".into(), negated }));
+                    continue;
+                }
+            }
+        }
+
+        let (word, quoted) = if chars.peek() == Some(&'"') {
+            chars.next();
+            let mut phrase = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(c) => phrase.push(c),
+                    None => return Err("Unclosed quote".into()),
+                }
+            }
+            if phrase.trim().is_empty() {
+                return Err("Quoted phrase is empty".into());
+            }
+            (phrase, true)
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            (word, false)
+        };
+
+        let token = match (word.as_str(), quoted, negated) {
+            ("AND", false, false) => Token::Operator(Operator::And),
+            ("OR", false, false) => Token::Operator(Operator::Or),
+            _ => Token::Term(SearchTerm { phrase: word.to_lowercase(), negated }),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(phrase: &str, negated: bool) -> Token {
+        Token::Term(SearchTerm { phrase: phrase.into(), negated })
+    }
+
+    fn alternatives(query: &str) -> Vec<Vec<SearchTerm>> {
+        match query.parse::<SearchQuery>().unwrap().kind {
+            SearchKind::Boolean(alternatives) => alternatives,
+            SearchKind::Regex(regex) => panic!("{query:?} parsed as regex {regex}"),
+        }
+    }
+
+    /// Render alternatives as e.g. `a & -b | c`, for readable assertions.
+    fn render(alternatives: &[Vec<SearchTerm>]) -> String {
+        alternatives
+            .iter()
+            .map(|terms| {
+                terms
+                    .iter()
+                    .map(|t| format!("{}{}", if t.negated { "-" } else { "" }, t.phrase))
+                    .collect::<Vec<_>>()
+                    .join(" & ")
+            })
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+
+    #[test]
+    fn tokenizes_quoted_phrases() {
+        let tokens = tokenize(r#""Secure Shell" OR "AND""#).unwrap();
+        let expected = vec![
+            term("secure shell", false),
+            Token::Operator(Operator::Or),
+            term("and", false),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn tokenizes_negation() {
+        let tokens = tokenize(r#"kafka -zookeeper -"secure shell" - -AND"#).unwrap();
+        let expected = vec![
+            term("kafka", false),
+            term("zookeeper", true),
+            term("secure shell", true),
+            term("-", false),
+            term("and", true),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn lowercase_operators_are_terms() {
+        let tokens = tokenize("kafka and zookeeper or").unwrap();
+        assert!(tokens.iter().all(|token| matches!(token, Token::Term(_))));
+    }
+
+    #[test]
+    fn unclosed_or_empty_quote_is_error() {
+        assert!(tokenize(r#"kafka "zoo"#).is_err());
+        assert!(tokenize(r#"kafka "  ""#).is_err());
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let parsed = alternatives("a OR b AND c OR -d e");
+        assert_eq!(render(&parsed), "a | b & c | -d & e");
+    }
+
+    #[test]
+    fn words_are_implicitly_combined_with_and() {
+        let parsed = alternatives("secure shell");
+        assert_eq!(render(&parsed), "secure & shell");
+
+        let query: SearchQuery = "secure shell".parse().unwrap();
+        assert!(query.matches(|phrase| "the secure shell".contains(phrase), |_| false));
+        assert!(query.matches(|phrase| "shell is secure".contains(phrase), |_| false));
+        assert!(!query.matches(|phrase| "secure copy".contains(phrase), |_| false));
+    }
+
+    #[test]
+    fn misplaced_operator_is_error() {
+        for (query, op) in [
+            ("AND", "AND"),
+            ("OR", "OR"),
+            ("kafka AND", "AND"),
+            ("OR kafka", "OR"),
+            ("kafka AND OR zookeeper", "AND"),
+        ] {
+            let err = query.parse::<SearchQuery>().unwrap_err();
+            let expected = format!(r#"Expected a search term on both sides of "{op}""#);
+            assert_eq!(err, expected, "query {query:?}");
+        }
+    }
+
+    #[test]
+    fn empty_search_is_error() {
+        assert_eq!("  ".parse::<SearchQuery>().unwrap_err(), "Search is empty");
+    }
+
+    #[test]
+    fn parses_case_insensitive_regex() {
+        let query: SearchQuery = "/postgres(ql)?/".parse().unwrap();
+        assert!(query.matches(|_| false, |regex| regex.is_match("PostgreSQL")));
+    }
+
+    #[test]
+    fn invalid_regex_is_error() {
+        let err = "/postgres(ql/".parse::<SearchQuery>().unwrap_err();
+        assert!(err.starts_with("Invalid regex: "), "{err}");
+        assert_eq!("//".parse::<SearchQuery>().unwrap_err(), "Regex is empty");
+    }
+}
@@ -0,0 +1,221 @@
+//! The HTTP API server.
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use color_eyre::eyre::Context;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, sync::RwLock};
+
+use crate::{
+    display::{CacheListOutput, CachedRevisionOutput, Output},
+    load::Loader,
+    query::UserQuery,
+    source::{get_latest_cached_revision, list_cache},
+    store::{DataSource, PortDatabase, QueryFilter, ServiceIndex},
+};
+
+/// Everything the server needs to answer requests.
+#[derive(Debug)]
+pub struct ServerState {
+    /// Replaced when a newer revision is found in the cache.
+    pub dbs: RwLock<Vec<PortDatabase>>,
+    /// Used to resolve service names.
+    pub service_index: ServiceIndex,
+    /// Which use cases are returned.
+    pub filter: QueryFilter,
+    /// Whether links are included in descriptions.
+    pub show_links: bool,
+    /// Whether notes and references are included in descriptions.
+    pub show_notes_and_references: bool,
+    /// Loads newer revisions found in its cache directory.
+    pub loader: Loader,
+}
+impl ServerState {
+    /// Answer a query with every loaded database, as JSON.
+    async fn query(&self, query: UserQuery) -> Result<Response, ApiError> {
+        let dbs = self.dbs.read().await;
+        let outputs = dbs
+            .iter()
+            .map(|db| {
+                db.query(
+                    query.clone(),
+                    &self.service_index,
+                    &self.filter,
+                    self.show_links,
+                    self.show_notes_and_references,
+                )
+            })
+            .collect::<Vec<_>>();
+        // outputs borrow from the databases, so serialise before releasing the lock
+        json_response(&outputs)
+    }
+
+    /// Load the latest cached revision of the Wikipedia page if it is newer
+    /// than the loaded one.
+    async fn reload_if_newer(&self) -> color_eyre::Result<()> {
+        let loaded = self.dbs.read().await.iter().find_map(|db| match db.source {
+            DataSource::Wikipedia { revision } => Some(revision),
+            _ => None,
+        });
+        let Some(loaded) = loaded else {
+            return Ok(()); // Wikipedia is not a loaded source
+        };
+
+        let latest = get_latest_cached_revision(&self.loader.options().cache_dir).await?;
+        if latest <= loaded {
+            debug!("Revision {loaded} is still the latest cached revision");
+            return Ok(());
+        }
+
+        let db = self.loader.load_revision(Some(latest)).await?;
+        let mut dbs = self.dbs.write().await;
+        for slot in dbs.iter_mut() {
+            if matches!(slot.source, DataSource::Wikipedia { .. }) {
+                *slot = db;
+                break;
+            }
+        }
+        info!("Reloaded from revision {loaded} to revision {latest}");
+        Ok(())
+    }
+}
+
+/// An error response, serialised as `{"error": "..."}`.
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+impl ApiError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+        }
+    }
+
+    fn internal(err: impl std::fmt::Display) -> Self {
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: err.to_string(),
+        }
+    }
+}
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({ "error": self.message });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+/// Serialise a response body as JSON, in the same field order as JSON output
+/// on the command line.
+fn json_response(body: &impl Serialize) -> Result<Response, ApiError> {
+    let body = serde_json::to_string(body).map_err(ApiError::internal)?;
+    Ok(([(header::CONTENT_TYPE, "application/json")], body).into_response())
+}
+
+/// Query parameters of `/search`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+struct SearchParams {
+    q: String,
+}
+
+/// Serve the API until interrupted.
+///
+/// If `reload_interval` is present, the cache is checked periodically for a
+/// newer revision of the Wikipedia page.
+pub async fn serve(
+    listen: SocketAddr,
+    state: ServerState,
+    reload_interval: Option<Duration>,
+) -> color_eyre::Result<()> {
+    let state = Arc::new(state);
+
+    if let Some(interval) = reload_interval {
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                if let Err(err) = state.reload_if_newer().await {
+                    warn!("Failed to check for a newer revision: {err:#}");
+                }
+            }
+        });
+    }
+
+    let app = Router::new()
+        .route("/lookup/{port}", get(lookup))
+        .route("/lookup/{port}/{protocol}", get(lookup_with_protocol))
+        .route("/search", get(search))
+        .route("/revisions", get(revisions))
+        .with_state(state);
+
+    let listener = TcpListener::bind(listen)
+        .await
+        .wrap_err_with(|| format!("Failed to listen on {listen}"))?;
+    println!("Listening on http://{}", listener.local_addr()?);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await
+        .wrap_err("Server error")
+}
+
+/// `/lookup/{port}`, where `port` is a port number or range, optionally with
+/// a protocol, e.g. `443/udp`.
+async fn lookup(
+    State(state): State<Arc<ServerState>>,
+    Path(port): Path<String>,
+) -> Result<Response, ApiError> {
+    // report the error of whichever syntax the user is likely using
+    let query = if port.contains('-') {
+        UserQuery::RangeLookup(port.parse().map_err(ApiError::bad_request)?)
+    } else {
+        UserQuery::PortLookup(port.parse().map_err(ApiError::bad_request)?)
+    };
+    state.query(query).await
+}
+
+/// `/lookup/{port}/{protocol}`.
+async fn lookup_with_protocol(
+    state: State<Arc<ServerState>>,
+    Path((port, protocol)): Path<(String, String)>,
+) -> Result<Response, ApiError> {
+    lookup(state, Path(format!("{port}/{protocol}"))).await
+}
+
+/// `/search?q=...`, which looks up the ports of a service if the term is its
+/// exact name.
+async fn search(
+    State(state): State<Arc<ServerState>>,
+    Query(SearchParams { q }): Query<SearchParams>,
+) -> Result<Response, ApiError> {
+    if q.trim().is_empty() {
+        return Err(ApiError::bad_request("Search term is empty"));
+    }
+    let query = q.parse().map_err(ApiError::bad_request)?;
+    state.query(UserQuery::Search(query)).await
+}
+
+/// `/revisions`, which lists the cached revisions.
+async fn revisions(State(state): State<Arc<ServerState>>) -> Result<Response, ApiError> {
+    let revisions = list_cache(&state.loader.options().cache_dir)
+        .await
+        .map_err(ApiError::internal)?
+        .into_iter()
+        .map(CachedRevisionOutput::from)
+        .collect();
+    json_response(&Output::from(CacheListOutput { revisions }))
+}
@@ -0,0 +1,130 @@
+//! Parsing services files, typically `/etc/services`.
+
+use std::{collections::HashMap, iter, path::Path};
+
+use log::{debug, warn};
+
+use crate::{
+    parse::RichTextSpan,
+    store::{DataSource, PortDatabase, PortRangeInfo, PortType},
+};
+
+/// A single entry of a services file, e.g. `ssh 22/tcp # SSH Remote Login Protocol`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ServiceEntry<'a> {
+    name: &'a str,
+    port: u16,
+    protocol: &'a str,
+    aliases: Vec<&'a str>,
+    comment: Option<&'a str>,
+}
+impl<'a> ServiceEntry<'a> {
+    /// Parse a single line of a services file.
+    ///
+    /// Returns `Ok(None)` if the line contains no entry.
+    fn parse(line: &'a str) -> Result<Option<Self>, String> {
+        let (content, comment) = match line.split_once('#') {
+            Some((content, comment)) => {
+                let comment = comment.trim();
+                (content, (!comment.is_empty()).then_some(comment))
+            }
+            None => (line, None),
+        };
+
+        let mut tokens = content.split_whitespace();
+        let Some(name) = tokens.next() else {
+            return Ok(None); // empty or comment-only line
+        };
+        let port_str = tokens
+            .next()
+            .ok_or_else(|| format!(r#"Service "{name}" has no port"#))?;
+        let (number_str, protocol) = port_str
+            .split_once('/')
+            .ok_or_else(|| format!(r#""{port_str}" is not a port-protocol pair"#))?;
+        let port = number_str
+            .parse()
+            .map_err(|_| format!(r#""{number_str}" is not a valid port number"#))?;
+        let aliases = tokens.collect();
+
+        Ok(Some(Self { name, port, protocol, aliases, comment }))
+    }
+
+    /// Generate a rich description, so that the entry can be displayed and
+    /// searched like a Wikipedia entry.
+    fn rich_description(&self) -> Vec<RichTextSpan> {
+        use RichTextSpan as Span;
+
+        let mut spans = vec![Span::Code { text: self.name.into() }];
+        if !self.aliases.is_empty() {
+            let text = format!(" (aliases: {})", self.aliases.join(", "));
+            spans.push(Span::Text { text });
+        }
+        if let Some(comment) = self.comment {
+            spans.push(Span::Text { text: format!(" - {comment}") });
+        }
+        spans
+    }
+}
+
+/// Parse a services file, typically `/etc/services`.
+///
+/// Entries of the same service and port on different protocols are merged
+/// into a single use case. Malformed lines are skipped.
+pub fn parse_services_file(content: &str, path: &Path) -> PortDatabase {
+    let mut list: Vec<PortRangeInfo> = vec![];
+    // (name, port) -> index in list
+    let mut seen: HashMap<(&str, u16), usize> = HashMap::new();
+
+    for (line_idx, line) in content.lines().enumerate() {
+        let entry = match ServiceEntry::parse(line) {
+            Ok(Some(entry)) => entry,
+            Ok(None) => continue,
+            Err(err) => {
+                warn!("Skipping line {} of {path:?}: {err}", line_idx + 1);
+                continue;
+            }
+        };
+
+        let idx = *seen.entry((entry.name, entry.port)).or_insert_with(|| {
+            list.push(PortRangeInfo {
+                number: entry.port..=entry.port,
+                tcp_type: PortType::Unused,
+                udp_type: PortType::Unused,
+                sctp_type: PortType::Unused,
+                dccp_type: PortType::Unused,
+                rich_description: entry.rich_description(),
+                service_names: iter::once(entry.name)
+                    .chain(entry.aliases.iter().copied())
+                    .map(Into::into)
+                    .collect(),
+                registration: None,
+            });
+            list.len() - 1
+        });
+        let info = &mut list[idx];
+        match entry.protocol {
+            "tcp" => info.tcp_type = PortType::Assigned,
+            "udp" => info.udp_type = PortType::Assigned,
+            "sctp" => info.sctp_type = PortType::Assigned,
+            "dccp" => info.dccp_type = PortType::Assigned,
+            // e.g. `ddp` in some distributions
+            p => debug!(
+                r#"Ignoring unsupported protocol "{p}" on line {} of {path:?}"#,
+                line_idx + 1
+            ),
+        }
+    }
+
+    // drop entries that only had unsupported protocols
+    list.retain(|info| {
+        [info.tcp_type, info.udp_type, info.sctp_type, info.dccp_type]
+            .iter()
+            .any(|t| !t.is_unused())
+    });
+
+    PortDatabase {
+        source: DataSource::Services { path: path.to_owned() },
+        ports: list,
+        urls: Default::default(),
+    }
+}
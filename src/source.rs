@@ -1,10 +1,59 @@
-use std::path::{Path, PathBuf};
+//! Loading sources from the network or the local cache.
 
-use color_eyre::eyre::{Context, OptionExt};
-use serde::Deserialize;
-use tokio::fs;
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 
-use crate::consts::{HISTORY_API_URL, PAGE_URL};
+use color_eyre::eyre::{bail, Context, OptionExt};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use log::{debug, info, warn};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, TimestampSeconds};
+use tokio::{fs, task::JoinHandle};
+
+#[cfg(feature = "bundled-snapshot")]
+use crate::store::DataSource;
+use crate::{
+    consts::{HISTORY_API_URL, IANA_REGISTRY_URL, ORIGIN_BASE_URL, PAGE_URL, PARSER_VERSION},
+    display::RevisionVerification,
+    http::HttpClient,
+    iana::parse_iana_registry,
+    parse::parse_page,
+    services::parse_services_file,
+    store::PortDatabase,
+};
+
+/// The URLs of the data sources.
+///
+/// These can be overridden, e.g. to use a mirror.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceUrls {
+    /// The history API URL for the source page.
+    pub history_api: String,
+    /// The URL for the source page.
+    pub page: String,
+    /// The base URL for same-site links.
+    pub origin_base: String,
+    /// The URL for the IANA registry in CSV form.
+    pub iana_registry: String,
+}
+impl Default for SourceUrls {
+    fn default() -> Self {
+        Self {
+            history_api: HISTORY_API_URL.into(),
+            page: PAGE_URL.into(),
+            origin_base: ORIGIN_BASE_URL.into(),
+            iana_registry: IANA_REGISTRY_URL.into(),
+        }
+    }
+}
 
 /// Representation of the revision number in history API's response.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
@@ -33,124 +82,1076 @@ impl From<HistoryApiResponse> for RevisionList {
 #[serde(from = "HistoryApiResponse")]
 struct RevisionList(Vec<u64>);
 
+/// The last response of the history API, cached for conditional requests.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedHistory {
+    /// The URL the response was retrieved from.
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    revisions: Vec<u64>,
+}
+
+/// Get the local path for the cached history API response.
+///
+/// This function does not perform any verification that this path exists.
+fn get_cached_history_path(cache_dir: impl AsRef<Path>) -> PathBuf {
+    cache_dir.as_ref().join("history-api.json")
+}
+
+/// Read the cached history API response, if it was retrieved from this URL.
+async fn read_cached_history(cache_dir: impl AsRef<Path>, url: &str) -> Option<CachedHistory> {
+    let path = get_cached_history_path(cache_dir);
+    let content = fs::read(&path).await.ok()?;
+    match serde_json::from_slice::<CachedHistory>(&content) {
+        Ok(cached) if cached.url == url => Some(cached),
+        Ok(_) => None,
+        Err(err) => {
+            warn!("Ignoring corrupted history cache at {path:?}: {err}");
+            None
+        }
+    }
+}
+
+/// Write the history API response to the local cache.
+async fn write_cached_history(
+    cache_dir: impl AsRef<Path>,
+    cached: &CachedHistory,
+) -> color_eyre::Result<()> {
+    let cache_dir = cache_dir.as_ref();
+    fs::create_dir_all(cache_dir).await?;
+    fs::write(
+        get_cached_history_path(cache_dir),
+        serde_json::to_vec(cached)?,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Query Wikipedia to find out the IDs of the most recent page revisions,
+/// newest first.
+///
+/// The request is conditional on the last response, so that an unchanged
+/// history is cheap to check.
+async fn query_recent_revisions(
+    cache_dir: impl AsRef<Path>,
+    client: &HttpClient,
+    urls: &SourceUrls,
+) -> color_eyre::Result<Vec<u64>> {
+    let cache_dir = cache_dir.as_ref();
+
+    let cached = read_cached_history(cache_dir, &urls.history_api).await;
+    let mut headers = HeaderMap::new();
+    if let Some(cached) = &cached {
+        if let Some(etag) = cached.etag.as_deref().and_then(|v| v.parse().ok()) {
+            headers.insert(IF_NONE_MATCH, etag);
+        }
+        if let Some(date) = cached.last_modified.as_deref().and_then(|v| v.parse().ok()) {
+            headers.insert(IF_MODIFIED_SINCE, date);
+        }
+    }
+
+    let res = client.get(&urls.history_api, headers).await?;
+    if res.status() == StatusCode::NOT_MODIFIED {
+        if let Some(cached) = cached {
+            debug!("Revision history is unchanged since the last query");
+            return Ok(cached.revisions);
+        }
+        bail!("Received {} for an unconditional request", res.status());
+    }
+
+    let header_str = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|v: &HeaderValue| v.to_str().ok())
+            .map(String::from)
+    };
+    let etag = header_str(ETAG);
+    let last_modified = header_str(LAST_MODIFIED);
+    let RevisionList(revisions) = res.json().await?;
+
+    // caching is only useful if the server supports conditional requests
+    if etag.is_some() || last_modified.is_some() {
+        let cached = CachedHistory {
+            url: urls.history_api.clone(),
+            etag,
+            last_modified,
+            revisions: revisions.clone(),
+        };
+        if let Err(err) = write_cached_history(cache_dir, &cached).await {
+            warn!("Failed to cache revision history: {err}");
+        }
+    }
+
+    Ok(revisions)
+}
+
 /// Query Wikipedia to find out the ID of the latest page revision.
-async fn query_latest_revision(client: &reqwest::Client) -> color_eyre::Result<u64> {
-    let list: RevisionList = client
-        .get(HISTORY_API_URL)
-        .send()
-        .await?
-        .error_for_status()?
-        .json()
-        .await?;
-    let latest = list.0.first().ok_or_eyre("Revision history is empty")?;
+async fn query_latest_revision(
+    cache_dir: impl AsRef<Path>,
+    client: &HttpClient,
+    urls: &SourceUrls,
+) -> color_eyre::Result<u64> {
+    let list = query_recent_revisions(cache_dir, client, urls).await?;
+    let latest = list.first().ok_or_eyre("Revision history is empty")?;
     Ok(*latest)
 }
 
-/// Get the latest cached revision.
-async fn get_latest_cached_revision(cache_dir: impl AsRef<Path>) -> color_eyre::Result<u64> {
+/// List all cached revisions, oldest first.
+async fn list_cached_revisions(cache_dir: impl AsRef<Path>) -> color_eyre::Result<Vec<u64>> {
     let cache_dir = cache_dir.as_ref();
 
-    let mut max_rev = None;
+    let mut revisions = vec![];
 
     let mut read_dir = fs::read_dir(cache_dir).await?;
     while let Some(entry) = read_dir.next_entry().await? {
-        let file_path = entry.path();
-        let Some(rev) = file_path
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .and_then(|s| s.parse().ok())
-        else {
+        let Some(rev) = entry.file_name().to_str().and_then(parse_page_file_name) else {
             continue; // ignore files with bad names
         };
-        if max_rev.unwrap_or_default() < rev {
-            max_rev = Some(rev);
-        }
+        revisions.push(rev);
     }
 
-    max_rev.ok_or_eyre("No cached pages found; please run with `-p|--pull` first")
+    revisions.sort_unstable();
+    revisions.dedup();
+    Ok(revisions)
+}
+
+/// Get the latest cached revision.
+pub async fn get_latest_cached_revision(cache_dir: impl AsRef<Path>) -> color_eyre::Result<u64> {
+    let revisions = list_cached_revisions(cache_dir).await?;
+    revisions
+        .last()
+        .copied()
+        .ok_or_eyre("No cached pages found; please run with `-p|--pull` first")
 }
 
 /// Get the local path for a revision.
 ///
+/// Pages are stored gzip-compressed.
+///
 /// This function does not perform any verification that this path exists.
 fn get_revision_path(cache_dir: impl AsRef<Path>, revision: u64) -> PathBuf {
+    cache_dir.as_ref().join(format!("{revision}.html.gz"))
+}
+
+/// Get the local path for a revision stored uncompressed by older versions.
+///
+/// This function does not perform any verification that this path exists.
+fn get_legacy_revision_path(cache_dir: impl AsRef<Path>, revision: u64) -> PathBuf {
     cache_dir.as_ref().join(format!("{revision}.html"))
 }
 
-/// Get and cache a Wikipedia page from the network.
+/// Get the revision of a cached page from its file name.
 ///
-/// If a revision is absent, we query and fetch the newest revision.
+/// Both compressed (e.g. `123.html.gz`) and legacy (e.g. `123.html`) pages
+/// are recognised.
+fn parse_page_file_name(name: &str) -> Option<u64> {
+    let stem = name
+        .strip_suffix(".html.gz")
+        .or_else(|| name.strip_suffix(".html"))?;
+    stem.parse().ok()
+}
+
+/// Get the local path for the metadata of a revision.
+///
+/// This function does not perform any verification that this path exists.
+fn get_revision_meta_path(cache_dir: impl AsRef<Path>, revision: u64) -> PathBuf {
+    cache_dir.as_ref().join(format!("{revision}.meta.json"))
+}
+
+/// Metadata of a cached revision.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct RevisionMeta {
+    /// When the page was fetched from the network.
+    #[serde_as(as = "TimestampSeconds<i64>")]
+    fetched: SystemTime,
+    /// When the revision was last confirmed to be the latest revision.
+    #[serde_as(as = "Option<TimestampSeconds<i64>>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checked: Option<SystemTime>,
+}
+
+/// Write the metadata of a revision.
+async fn write_revision_meta(
+    cache_dir: impl AsRef<Path>,
+    revision: u64,
+    meta: &RevisionMeta,
+) -> color_eyre::Result<()> {
+    let meta_path = get_revision_meta_path(cache_dir, revision);
+    fs::write(&meta_path, serde_json::to_vec(meta)?).await?;
+    Ok(())
+}
+
+/// Read the metadata of a revision, if it has been recorded.
+async fn read_revision_meta(cache_dir: impl AsRef<Path>, revision: u64) -> Option<RevisionMeta> {
+    let meta_path = get_revision_meta_path(cache_dir, revision);
+    if !meta_path.exists() {
+        return None;
+    }
+    let meta_res = async {
+        let meta: RevisionMeta = serde_json::from_slice(&fs::read(&meta_path).await?)?;
+        color_eyre::Result::<_>::Ok(meta)
+    }
+    .await;
+    match meta_res {
+        Ok(meta) => Some(meta),
+        Err(err) => {
+            warn!("Revision metadata at {meta_path:?} is corrupted: {err}");
+            None
+        }
+    }
+}
+
+/// Record that a cached revision has just been confirmed to be the latest revision.
+async fn mark_revision_checked(
+    cache_dir: impl AsRef<Path>,
+    revision: u64,
+) -> color_eyre::Result<()> {
+    let cache_dir = cache_dir.as_ref();
+
+    let fetched = match read_revision_meta(cache_dir, revision).await {
+        Some(meta) => meta.fetched,
+        None => get_page_modified_time(cache_dir, revision).await?,
+    };
+    let meta = RevisionMeta {
+        fetched,
+        checked: Some(SystemTime::now()),
+    };
+    write_revision_meta(cache_dir, revision, &meta).await
+}
+
+/// Get when a cached revision was last confirmed to be the latest revision.
+///
+/// If it has never been confirmed since it was fetched, the fetch time is used
+/// instead. For pages cached before fetch times were recorded, the modification
+/// time of the page is used instead.
+pub async fn get_revision_check_time(
+    cache_dir: impl AsRef<Path>,
+    revision: u64,
+) -> color_eyre::Result<SystemTime> {
+    let cache_dir = cache_dir.as_ref();
+
+    match read_revision_meta(cache_dir, revision).await {
+        Some(meta) => Ok(meta.checked.unwrap_or(meta.fetched)),
+        None => get_page_modified_time(cache_dir, revision).await,
+    }
+}
+
+/// Get the modification time of a cached page.
+async fn get_page_modified_time(
+    cache_dir: impl AsRef<Path>,
+    revision: u64,
+) -> color_eyre::Result<SystemTime> {
+    let cache_dir = cache_dir.as_ref();
+
+    let page_path = [
+        get_revision_path(cache_dir, revision),
+        get_legacy_revision_path(cache_dir, revision),
+    ]
+    .into_iter()
+    .find(|path| path.exists())
+    .ok_or_eyre(format!("Revision {revision} is not cached"))?;
+    let modified = fs::metadata(&page_path)
+        .await
+        .and_then(|m| m.modified())
+        .wrap_err_with(|| format!("Failed to read the modification time of {page_path:?}"))?;
+    Ok(modified)
+}
+
+/// Read a cached page, decompressing it if necessary.
+async fn read_cached_page(page_path: &Path) -> color_eyre::Result<String> {
+    let content = fs::read(page_path).await?;
+    let page = if page_path.extension().is_some_and(|ext| ext == "gz") {
+        let mut page = String::new();
+        GzDecoder::new(content.as_slice())
+            .read_to_string(&mut page)
+            .wrap_err("Failed to decompress page")?;
+        page
+    } else {
+        String::from_utf8(content)?
+    };
+    Ok(page)
+}
+
+/// Compress and write a page to the cache.
+async fn write_cached_page(page_path: &Path, page: &str) -> color_eyre::Result<()> {
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(page.as_bytes())?;
+    let content = encoder.finish().wrap_err("Failed to compress page")?;
+    fs::write(page_path, content).await?;
+    Ok(())
+}
+
+/// Compress all pages cached uncompressed by older versions.
+///
+/// Failure to migrate is not fatal and is only logged.
+pub async fn migrate_legacy_pages(cache_dir: impl AsRef<Path>) {
+    let cache_dir = cache_dir.as_ref();
+
+    let Ok(mut read_dir) = fs::read_dir(cache_dir).await else {
+        return; // nothing cached yet
+    };
+    while let Ok(Some(entry)) = read_dir.next_entry().await {
+        let Some(revision) = entry
+            .file_name()
+            .to_str()
+            .filter(|name| name.ends_with(".html"))
+            .and_then(parse_page_file_name)
+        else {
+            continue;
+        };
+
+        let legacy_path = entry.path();
+        let page_path = get_revision_path(cache_dir, revision);
+        let migrate_res = async {
+            // the modification time is the best guess of when it was fetched
+            let fetched = entry.metadata().await?.modified()?;
+            let meta = RevisionMeta { fetched, checked: None };
+            write_revision_meta(cache_dir, revision, &meta).await?;
+
+            let page = read_cached_page(&legacy_path).await?;
+            write_cached_page(&page_path, &page).await?;
+            fs::remove_file(&legacy_path).await?;
+            color_eyre::Result::<()>::Ok(())
+        }
+        .await;
+        match migrate_res {
+            Ok(()) => info!("Compressed cached page {legacy_path:?} to {page_path:?}"),
+            Err(err) => warn!("Failed to compress cached page {legacy_path:?}: {err}"),
+        }
+    }
+}
+
+/// Get the local path for the parsed database of a revision.
+///
+/// The path is specific to the current parser version, so that cached results
+/// of an outdated parser are never used.
+///
+/// This function does not perform any verification that this path exists.
+fn get_parsed_database_path(cache_dir: impl AsRef<Path>, revision: u64) -> PathBuf {
+    cache_dir
+        .as_ref()
+        .join(format!("{revision}.parsed-{PARSER_VERSION}.json"))
+}
+
+/// Load the cached parsed database of a revision.
+///
+/// Returns `None` if it does not exist or cannot be read, in which case the
+/// page should be parsed again.
+async fn load_parsed_database(cache_dir: impl AsRef<Path>, revision: u64) -> Option<PortDatabase> {
+    let db_path = get_parsed_database_path(cache_dir, revision);
+    if !db_path.exists() {
+        return None;
+    }
+
+    let content = match fs::read(&db_path).await {
+        Ok(c) => c,
+        Err(err) => {
+            warn!("Failed to read cached parsed database at {db_path:?}: {err}");
+            return None;
+        }
+    };
+    match serde_json::from_slice(&content) {
+        Ok(db) => {
+            debug!("Loaded cached parsed database from {db_path:?}");
+            Some(db)
+        }
+        Err(err) => {
+            warn!("Cached parsed database at {db_path:?} is corrupted: {err}");
+            None
+        }
+    }
+}
+
+/// Parse a page and cache the parsed database.
+///
+/// Failure to cache is not fatal and is only logged.
+async fn parse_and_cache_page(
+    cache_dir: impl AsRef<Path>,
+    revision: u64,
+    page: &str,
+) -> color_eyre::Result<PortDatabase> {
+    let db = parse_page(page, revision).wrap_err("Failed to parse Wikipedia page")?;
+    cache_parsed_database(cache_dir, revision, &db).await;
+    Ok(db)
+}
+
+/// Cache the parsed database of a revision.
+///
+/// Failure to cache is not fatal and is only logged.
+async fn cache_parsed_database(cache_dir: impl AsRef<Path>, revision: u64, db: &PortDatabase) {
+    let db_path = get_parsed_database_path(cache_dir, revision);
+    let write_res = match serde_json::to_vec(db) {
+        Ok(content) => fs::write(&db_path, content).await.map_err(Into::into),
+        Err(err) => Err(color_eyre::Report::from(err)),
+    };
+    if let Err(err) = write_res {
+        warn!("Failed to cache parsed database at {db_path:?}: {err}");
+    }
+}
+
+/// Get the parsed database of a Wikipedia page, retrieving the page from the
+/// network if necessary.
+///
+/// If a revision is absent, we query and use the newest revision.
 ///
-/// Returns the path to and content of the cached page.
 /// Errors if we encounter network problems, or if the revision is invalid.
-pub async fn get_wikipedia_page_online(
+pub async fn get_database_online(
     cache_dir: impl AsRef<Path>,
-    client: &reqwest::Client,
+    client: &HttpClient,
+    urls: &SourceUrls,
     revision: Option<u64>,
-) -> color_eyre::Result<(PathBuf, String)> {
+) -> color_eyre::Result<PortDatabase> {
     let cache_dir = cache_dir.as_ref();
 
-    // get revision
+    let is_latest = revision.is_none();
     let revision = match revision {
         Some(rev) => rev,
-        None => query_latest_revision(client)
+        None => query_latest_revision(cache_dir, client, urls)
             .await
             .wrap_err("Failed to query latest revision. Network issue?")?,
     };
 
+    let db = match load_parsed_database(cache_dir, revision).await {
+        Some(db) => db,
+        None => {
+            let (_page_path, page) = get_wikipedia_page_online(cache_dir, client, urls, revision)
+                .await
+                .wrap_err("Failed to fetch Wikipedia page from network")?;
+            parse_and_cache_page(cache_dir, revision, &page).await?
+        }
+    };
+
+    // staleness is based on when the revision was last confirmed to be the latest,
+    // not when it was fetched
+    if is_latest {
+        if let Err(err) = mark_revision_checked(cache_dir, revision).await {
+            warn!("Failed to record the check time of revision {revision}: {err}");
+        }
+    }
+
+    Ok(db)
+}
+
+/// Get the parsed database of a Wikipedia page with network disabled.
+///
+/// If a revision is absent, we use the newest available revision.
+///
+/// Errors if the requested page is unavailable.
+pub async fn get_database_offline(
+    cache_dir: impl AsRef<Path>,
+    revision: Option<u64>,
+) -> color_eyre::Result<PortDatabase> {
+    let cache_dir = cache_dir.as_ref();
+
+    let revision = match revision {
+        Some(r) => r,
+        None => get_latest_cached_revision(cache_dir)
+            .await
+            .wrap_err("Failed to find the latest cached revision")?,
+    };
+
+    if let Some(db) = load_parsed_database(cache_dir, revision).await {
+        return Ok(db);
+    }
+
+    let (_page_path, page) = get_wikipedia_page_offline(cache_dir, revision)
+        .await
+        .wrap_err("Failed to fetch Wikipedia page from local cache")?;
+    parse_and_cache_page(cache_dir, revision, &page).await
+}
+
+/// Get the parsed databases of all cached revisions, or only the `last` few,
+/// oldest first.
+///
+/// If a client is provided, the latest revision listed by the history API is
+/// retrieved as well, or the `last` few if specified. Older revisions are never
+/// retrieved, since the history API lists many of them.
+///
+/// Revisions that fail to load are skipped with a warning.
+pub async fn get_all_databases(
+    cache_dir: impl AsRef<Path>,
+    client: Option<&HttpClient>,
+    urls: &SourceUrls,
+    last: Option<u32>,
+) -> color_eyre::Result<Vec<PortDatabase>> {
+    let last = last.map(|n| n as usize);
+    let cache_dir = cache_dir.as_ref();
+
+    let mut revisions = if cache_dir.exists() {
+        list_cached_revisions(cache_dir)
+            .await
+            .wrap_err("Failed to list cached revisions")?
+    } else {
+        vec![]
+    };
+    if let Some(client) = client {
+        let recent = query_recent_revisions(cache_dir, client, urls)
+            .await
+            .wrap_err("Failed to query recent revisions. Network issue?")?;
+        revisions.extend(recent.into_iter().take(last.unwrap_or(1)));
+        revisions.sort_unstable();
+        revisions.dedup();
+    }
+    if let Some(last) = last {
+        revisions.drain(..revisions.len().saturating_sub(last));
+    }
+    if revisions.is_empty() {
+        bail!("No cached pages found; please run with `-p|--pull` first");
+    }
+
+    let mut dbs = vec![];
+    for revision in revisions {
+        let db_res = match client {
+            Some(client) => get_database_online(cache_dir, client, urls, Some(revision)).await,
+            None => get_database_offline(cache_dir, Some(revision)).await,
+        };
+        match db_res {
+            Ok(db) => dbs.push(db),
+            Err(err) => warn!("Skipping revision {revision}: {err:#}"),
+        }
+    }
+    Ok(dbs)
+}
+
+/// The parsed database of the bundled Wikipedia page snapshot, as
+/// gzip-compressed JSON.
+///
+/// It is parsed when vendored by `snapshot/update.sh`, so it must be vendored
+/// again whenever the parsed database format changes.
+#[cfg(feature = "bundled-snapshot")]
+const SNAPSHOT_DATABASE: &[u8] = include_bytes!("../snapshot/database.json.gz");
+
+/// Get the parsed database of the Wikipedia page snapshot bundled into the binary.
+///
+/// This is the last resort when no revision is cached.
+#[cfg(feature = "bundled-snapshot")]
+pub fn get_snapshot_database() -> color_eyre::Result<PortDatabase> {
+    let mut db: PortDatabase = serde_json::from_reader(GzDecoder::new(SNAPSHOT_DATABASE))
+        .wrap_err("Bundled snapshot is corrupted; vendor it again with `snapshot/update.sh`")?;
+    let DataSource::Wikipedia { revision } = db.source else {
+        bail!("Bundled snapshot is not of the Wikipedia page");
+    };
+    db.source = DataSource::BundledSnapshot { revision };
+    Ok(db)
+}
+
+/// How long to wait for a background refresh before exiting without it.
+const REFRESH_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// A retrieval of the latest revision running in the background, started
+/// because the cached revision is stale.
+#[derive(Debug)]
+pub struct BackgroundRefresh {
+    handle: JoinHandle<color_eyre::Result<PortDatabase>>,
+}
+impl BackgroundRefresh {
+    /// Start retrieving the latest revision in the background.
+    pub fn spawn(cache_dir: impl Into<PathBuf>, client: HttpClient, urls: SourceUrls) -> Self {
+        let cache_dir = cache_dir.into();
+        let handle =
+            tokio::spawn(
+                async move { get_database_online(&cache_dir, &client, &urls, None).await },
+            );
+        Self { handle }
+    }
+
+    /// Give the retrieval a short while to finish, then abort it.
+    ///
+    /// The retrieval is only for next time, so exiting is never held up by a
+    /// slow network. Failure is not fatal and is only logged.
+    pub async fn finish(self) {
+        let Self { mut handle } = self;
+        let res = match tokio::time::timeout(REFRESH_GRACE_PERIOD, &mut handle).await {
+            Ok(res) => res.map_err(Into::into).and_then(|res| res),
+            Err(_) => {
+                handle.abort();
+                info!("Gave up retrieving the latest revision; it will be retried next time");
+                return;
+            }
+        };
+        match res {
+            Ok(db) => info!("Retrieved {} for next time", db.source),
+            Err(err) => warn!("Failed to retrieve the latest revision: {err:#}"),
+        }
+    }
+}
+
+/// Format the age of a revision in its largest whole unit, e.g. `42 days`.
+pub fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    let (n, unit) = match secs {
+        86400.. => (secs / 86400, "day"),
+        3600.. => (secs / 3600, "hour"),
+        _ => (secs / 60, "minute"),
+    };
+    let plural = if n == 1 { "" } else { "s" };
+    format!("{n} {unit}{plural}")
+}
+
+/// Get and cache a Wikipedia page from the network.
+///
+/// Returns the path to and content of the cached page.
+/// Errors if we encounter network problems, or if the revision is invalid.
+async fn get_wikipedia_page_online(
+    cache_dir: impl AsRef<Path>,
+    client: &HttpClient,
+    urls: &SourceUrls,
+    revision: u64,
+) -> color_eyre::Result<(PathBuf, String)> {
+    let cache_dir = cache_dir.as_ref();
+
     // use cached if exists
     let page_path = get_revision_path(cache_dir, revision);
-    if page_path.exists() {
-        let content = fs::read_to_string(&page_path)
-            .await
-            .wrap_err_with(|| format!("Failed to read cached page at {page_path:?}"))?;
-        return Ok((page_path, content));
+    if page_path.exists() || get_legacy_revision_path(cache_dir, revision).exists() {
+        return get_wikipedia_page_offline(cache_dir, revision).await;
     }
 
     // fetch
-    let url = format!("{PAGE_URL}?oldid={revision}");
-    let content = client
-        .get(url)
-        .send()
-        .await?
-        .error_for_status()?
-        .text()
-        .await?;
+    let url = format!("{}?oldid={revision}", urls.page);
+    let content = client.get(&url, HeaderMap::new()).await?.text().await?;
 
     // cache
     fs::create_dir_all(&cache_dir)
         .await
         .wrap_err_with(|| format!("Failed to create cache directory at {cache_dir:?}"))?;
-    fs::write(&page_path, &content)
+    write_cached_page(&page_path, &content)
         .await
         .wrap_err_with(|| format!("Failed to cache saved page at {page_path:?}"))?;
+    let meta = RevisionMeta {
+        fetched: SystemTime::now(),
+        checked: None,
+    };
+    if let Err(err) = write_revision_meta(cache_dir, revision, &meta).await {
+        warn!("Failed to record the fetch time of revision {revision}: {err}");
+    }
 
     Ok((page_path, content))
 }
 
 /// Get the Wikipedia page with network disabled.
 ///
-/// If a revision is absent, we return the newest available revision.
-///
 /// Returns the path to and content of the page.
 /// Errors if the requested page is unavailable.
-pub async fn get_wikipedia_page_offline(
+async fn get_wikipedia_page_offline(
     cache_dir: impl AsRef<Path>,
-    revision: Option<u64>,
+    revision: u64,
 ) -> color_eyre::Result<(PathBuf, String)> {
     let cache_dir = cache_dir.as_ref();
 
-    let revision = match revision {
-        Some(r) => r,
-        None => get_latest_cached_revision(cache_dir)
-            .await
-            .wrap_err("Failed to find the latest cached revision")?,
-    };
-
+    // fall back to uncompressed pages not yet migrated
     let page_path = get_revision_path(cache_dir, revision);
-    let content = fs::read_to_string(&page_path)
+    let legacy_path = get_legacy_revision_path(cache_dir, revision);
+    let page_path = if !page_path.exists() && legacy_path.exists() {
+        legacy_path
+    } else {
+        page_path
+    };
+    let content = read_cached_page(&page_path)
         .await
         .wrap_err_with(|| format!("Failed to read cached page at {page_path:?}"))?;
 
     Ok((page_path, content))
 }
+
+/// A Wikipedia page revision in the local cache.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CachedRevision {
+    /// The revision ID of the page.
+    pub revision: u64,
+    /// All cache files of this revision, including parsed databases.
+    pub files: Vec<PathBuf>,
+    /// The total size of all cache files of this revision.
+    pub size: u64,
+    /// When any cache file of this revision was last modified.
+    pub modified: SystemTime,
+}
+
+/// List all revisions in the local cache, oldest first.
+pub async fn list_cache(cache_dir: impl AsRef<Path>) -> color_eyre::Result<Vec<CachedRevision>> {
+    let cache_dir = cache_dir.as_ref();
+    if !cache_dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut revisions: BTreeMap<u64, CachedRevision> = BTreeMap::new();
+
+    let mut read_dir = fs::read_dir(cache_dir)
+        .await
+        .wrap_err_with(|| format!("Failed to read cache directory at {cache_dir:?}"))?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        // e.g. `123.html` or `123.parsed-0.5.6.json`
+        let Some(revision) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.split('.').next())
+            .and_then(|rev| rev.parse().ok())
+        else {
+            continue; // not a revision
+        };
+        let metadata = entry.metadata().await?;
+
+        let cached = revisions.entry(revision).or_insert_with(|| CachedRevision {
+            revision,
+            files: vec![],
+            size: 0,
+            modified: SystemTime::UNIX_EPOCH,
+        });
+        cached.files.push(entry.path());
+        cached.size += metadata.len();
+        cached.modified = cached.modified.max(metadata.modified()?);
+    }
+
+    Ok(revisions.into_values().collect())
+}
+
+/// Remove a revision from the local cache.
+///
+/// Errors if the revision is not cached.
+pub async fn remove_cached_revision(
+    cache_dir: impl AsRef<Path>,
+    revision: u64,
+) -> color_eyre::Result<CachedRevision> {
+    let Some(cached) = list_cache(cache_dir)
+        .await?
+        .into_iter()
+        .find(|c| c.revision == revision)
+    else {
+        bail!("Revision {revision} is not cached");
+    };
+    remove_cache_files(&cached).await?;
+    Ok(cached)
+}
+
+/// Remove some revisions from the local cache.
+///
+/// Errors on the first revision that is not cached, leaving the rest in place.
+pub async fn remove_cached_revisions(
+    cache_dir: impl AsRef<Path>,
+    revisions: &[u64],
+) -> color_eyre::Result<Vec<CachedRevision>> {
+    let cache_dir = cache_dir.as_ref();
+
+    let mut removed = vec![];
+    for &revision in revisions {
+        removed.push(remove_cached_revision(cache_dir, revision).await?);
+    }
+    Ok(removed)
+}
+
+/// Remove all but the newest `keep` revisions from the local cache.
+///
+/// Returns the removed revisions.
+pub async fn prune_cache(
+    cache_dir: impl AsRef<Path>,
+    keep: usize,
+) -> color_eyre::Result<Vec<CachedRevision>> {
+    let mut cached = list_cache(cache_dir).await?;
+    let remove_count = cached.len().saturating_sub(keep);
+
+    let removed = cached.drain(..remove_count).collect::<Vec<_>>();
+    for revision in removed.iter() {
+        remove_cache_files(revision).await?;
+    }
+    Ok(removed)
+}
+
+/// Remove all cache files of a revision.
+async fn remove_cache_files(cached: &CachedRevision) -> color_eyre::Result<()> {
+    for path in cached.files.iter() {
+        fs::remove_file(path)
+            .await
+            .wrap_err_with(|| format!("Failed to remove {path:?}"))?;
+        debug!("Removed {path:?}");
+    }
+    Ok(())
+}
+
+/// Parse a cached page again, bypassing and then replacing its cached
+/// parsed database.
+///
+/// Errors if the page is unavailable, cannot be parsed, or contains no use cases.
+pub async fn verify_cached_revision(
+    cache_dir: impl AsRef<Path>,
+    revision: u64,
+) -> color_eyre::Result<PortDatabase> {
+    let cache_dir = cache_dir.as_ref();
+
+    let (page_path, page) = get_wikipedia_page_offline(cache_dir, revision)
+        .await
+        .wrap_err("Failed to fetch Wikipedia page from local cache")?;
+    let db = parse_page(&page, revision).wrap_err("Failed to parse Wikipedia page")?;
+    if db.ports.is_empty() {
+        bail!("Found no port tables in cached page at {page_path:?}");
+    }
+    cache_parsed_database(cache_dir, revision, &db).await;
+    Ok(db)
+}
+
+/// Parse some cached pages again, or all of them if none are specified.
+///
+/// Unlike [`verify_cached_revision`], a failure is recorded in the result of
+/// its revision instead of being returned.
+pub async fn verify_cache(
+    cache_dir: impl AsRef<Path>,
+    revisions: &[u64],
+) -> color_eyre::Result<Vec<RevisionVerification>> {
+    let cache_dir = cache_dir.as_ref();
+
+    let revisions = if revisions.is_empty() {
+        list_cache(cache_dir)
+            .await?
+            .into_iter()
+            .map(|c| c.revision)
+            .collect()
+    } else {
+        revisions.to_vec()
+    };
+
+    let mut results = vec![];
+    for revision in revisions {
+        let result = match verify_cached_revision(cache_dir, revision).await {
+            Ok(db) => RevisionVerification {
+                revision,
+                use_cases: db.ports.len(),
+                unknown_spans: db.unknown_span_count(),
+                error: None,
+            },
+            Err(err) => RevisionVerification {
+                revision,
+                use_cases: 0,
+                unknown_spans: 0,
+                error: Some(format!("{err:#}")),
+            },
+        };
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// Get the local path for the cached IANA registry.
+///
+/// This function does not perform any verification that this path exists.
+fn get_iana_registry_path(cache_dir: impl AsRef<Path>) -> PathBuf {
+    cache_dir
+        .as_ref()
+        .join("iana-service-names-port-numbers.csv")
+}
+
+/// Get and cache the IANA registry from the network, then parse it.
+///
+/// Errors if we encounter network problems, or if the registry is malformed.
+pub async fn get_iana_database_online(
+    cache_dir: impl AsRef<Path>,
+    client: &HttpClient,
+    urls: &SourceUrls,
+) -> color_eyre::Result<PortDatabase> {
+    let cache_dir = cache_dir.as_ref();
+
+    // fetch
+    let content = client
+        .get(&urls.iana_registry, HeaderMap::new())
+        .await?
+        .text()
+        .await
+        .wrap_err("Failed to fetch IANA registry from network")?;
+
+    // cache
+    let registry_path = get_iana_registry_path(cache_dir);
+    fs::create_dir_all(&cache_dir)
+        .await
+        .wrap_err_with(|| format!("Failed to create cache directory at {cache_dir:?}"))?;
+    fs::write(&registry_path, &content)
+        .await
+        .wrap_err_with(|| format!("Failed to cache IANA registry at {registry_path:?}"))?;
+
+    parse_iana_registry(content.as_bytes()).wrap_err("Failed to parse IANA registry")
+}
+
+/// Get and parse the IANA registry with network disabled.
+///
+/// If a path is absent, we use the cached registry.
+///
+/// Errors if the registry is unavailable or malformed.
+pub async fn get_iana_database_offline(
+    cache_dir: impl AsRef<Path>,
+    path: Option<&Path>,
+) -> color_eyre::Result<PortDatabase> {
+    let registry_path = match path {
+        Some(p) => p.to_owned(),
+        None => get_iana_registry_path(cache_dir),
+    };
+    if !registry_path.exists() {
+        bail!("IANA registry not found at {registry_path:?}; please run with `-p|--pull` first");
+    }
+
+    let content = fs::read(&registry_path)
+        .await
+        .wrap_err_with(|| format!("Failed to read IANA registry at {registry_path:?}"))?;
+
+    parse_iana_registry(content.as_slice()).wrap_err("Failed to parse IANA registry")
+}
+
+/// Get and parse a local services file.
+///
+/// Errors if the file is unavailable.
+pub async fn get_services_database(path: impl AsRef<Path>) -> color_eyre::Result<PortDatabase> {
+    let path = path.as_ref();
+
+    let content = fs::read_to_string(path)
+        .await
+        .wrap_err_with(|| format!("Failed to read services file at {path:?}"))?;
+
+    Ok(parse_services_file(&content, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{header, header_exists, method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+    use crate::http::HttpOptions;
+
+    #[cfg(feature = "bundled-snapshot")]
+    #[test]
+    fn bundled_snapshot_is_valid() {
+        let db = get_snapshot_database().unwrap();
+        assert!(matches!(db.source, DataSource::BundledSnapshot { .. }));
+        assert!(!db.ports.is_empty());
+    }
+
+    const HISTORY: &str = r#"{"revisions":[{"id":400},{"id":300}]}"#;
+
+    async fn setup() -> (MockServer, HttpClient, SourceUrls) {
+        let server = MockServer::start().await;
+        let options = HttpOptions { retries: 0, ..Default::default() };
+        let client = HttpClient::new(&options).await.unwrap();
+        let urls = SourceUrls {
+            history_api: format!("{}/history", server.uri()),
+            page: format!("{}/page", server.uri()),
+            ..Default::default()
+        };
+        (server, client, urls)
+    }
+
+    #[tokio::test]
+    async fn unchanged_history_by_etag_is_cached() {
+        let (server, client, urls) = setup().await;
+        let cache_dir = tempfile::tempdir().unwrap();
+        Mock::given(method("GET"))
+            .and(path("/history"))
+            .and(header("If-None-Match", r#""v1""#))
+            .respond_with(ResponseTemplate::new(304))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/history"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", r#""v1""#)
+                    .set_body_string(HISTORY),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        for _ in 0..2 {
+            let revisions = query_recent_revisions(cache_dir.path(), &client, &urls)
+                .await
+                .unwrap();
+            assert_eq!(revisions, [400, 300]);
+        }
+    }
+
+    #[tokio::test]
+    async fn unchanged_history_by_date_is_cached() {
+        let (server, client, urls) = setup().await;
+        let cache_dir = tempfile::tempdir().unwrap();
+        let date = "Wed, 21 Oct 2015 07:28:00 GMT";
+        Mock::given(method("GET"))
+            .and(path("/history"))
+            // dates contain commas, which `header` treats as separators
+            .and(header_exists("If-Modified-Since"))
+            .respond_with(ResponseTemplate::new(304))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/history"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Last-Modified", date)
+                    .set_body_string(HISTORY),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        for _ in 0..2 {
+            let latest = query_latest_revision(cache_dir.path(), &client, &urls)
+                .await
+                .unwrap();
+            assert_eq!(latest, 400);
+        }
+    }
+
+    #[tokio::test]
+    async fn not_modified_without_cache_is_error() {
+        let (server, client, urls) = setup().await;
+        let cache_dir = tempfile::tempdir().unwrap();
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(304))
+            .mount(&server)
+            .await;
+
+        let res = query_recent_revisions(cache_dir.path(), &client, &urls).await;
+        assert!(res.is_err());
+    }
+
+    /// Mount the history, and count the requests for each revision of the page.
+    ///
+    /// Pages fail to load, so that only which revisions are requested matters.
+    async fn mount_history(server: &MockServer, requested: &[u64]) {
+        Mock::given(method("GET"))
+            .and(path("/history"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(HISTORY))
+            .mount(server)
+            .await;
+        for revision in [400, 300] {
+            let expected = if requested.contains(&revision) { 1 } else { 0 };
+            Mock::given(method("GET"))
+                .and(path("/page"))
+                .and(query_param("oldid", revision.to_string()))
+                .respond_with(ResponseTemplate::new(404))
+                .expect(expected)
+                .mount(server)
+                .await;
+        }
+    }
+
+    #[tokio::test]
+    async fn pulling_all_revisions_only_retrieves_latest() {
+        let (server, client, urls) = setup().await;
+        let cache_dir = tempfile::tempdir().unwrap();
+        mount_history(&server, &[400]).await;
+
+        let res = get_all_databases(cache_dir.path(), Some(&client), &urls, None).await;
+        assert!(res.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn pulling_last_revisions_retrieves_them() {
+        let (server, client, urls) = setup().await;
+        let cache_dir = tempfile::tempdir().unwrap();
+        mount_history(&server, &[400, 300]).await;
+
+        let res = get_all_databases(cache_dir.path(), Some(&client), &urls, Some(2)).await;
+        assert!(res.unwrap().is_empty());
+    }
+}
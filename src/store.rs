@@ -1,18 +1,32 @@
-use std::ops::RangeInclusive;
+//! The port database and the queries on it.
+
+use std::{collections::HashMap, fmt, ops::RangeInclusive, path::PathBuf};
 
 use color_eyre::eyre::bail;
 use itertools::Itertools;
+use regex::Regex;
 use scraper::ElementRef;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    cli::{PortSelection, SupportedProtocol},
-    display::{MatchedPort, PortLookupOutput, PortUseCase, SearchOutput},
+    annotate::find_ports,
+    display::{
+        AnnotatedLineOutput, DiffOutput, FreePortsOutput, HistoryEntry, HistoryOutput, LocalOutput,
+        LocalSocketOutput, MatchedPort, NmapHostOutput, NmapOutput, NmapPortOutput, Output,
+        PortAnnotation, PortChange, PortLookupOutput, PortUseCase, RangeLookupOutput, SearchOutput,
+        ServiceLookupOutput,
+    },
+    iana::IanaRegistration,
+    local::ListeningSocket,
+    nmap::{NmapHost, NmapPort},
     parse::RichTextSpan,
+    query::{PortRangeSelection, PortSelection, SupportedProtocol, UserQuery},
+    search::SearchQuery,
+    source::SourceUrls,
 };
 
 /// The type of port, as classified by Wikipedia.
-#[derive(Copy, Clone, Debug, PartialEq, Eq, strum::Display, Serialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, strum::Display, strum::EnumString, Serialize)]
 #[strum(serialize_all = "kebab-case")]
 #[serde(rename_all = "kebab-case")]
 pub enum PortCategory {
@@ -23,6 +37,16 @@ pub enum PortCategory {
     /// 49152 to 65535 (2^15 + 2^14 to 2^16 - 1).
     Dynamic,
 }
+impl PortCategory {
+    /// The range of ports in this category.
+    pub fn range(&self) -> RangeInclusive<u16> {
+        match self {
+            Self::WellKnown => 0..=1023,
+            Self::Registered => 1024..=49151,
+            Self::Dynamic => 49152..=65535,
+        }
+    }
+}
 impl From<u16> for PortCategory {
     fn from(port: u16) -> Self {
         use PortCategory as C;
@@ -51,7 +75,10 @@ impl TryFrom<&RangeInclusive<u16>> for PortCategory {
 }
 
 /// The port type as listed by Wikipedia.
-#[derive(Copy, Clone, Debug, PartialEq, Eq, strum::Display, Serialize)]
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, strum::Display, strum::EnumString, Serialize, Deserialize,
+)]
+#[strum(ascii_case_insensitive)]
 #[serde(rename_all = "kebab-case")]
 pub enum PortType {
     /// Described protocol is not used.
@@ -93,6 +120,7 @@ impl TryFrom<ElementRef<'_>> for PortType {
     }
 }
 impl PortType {
+    /// Whether the port is unused by this protocol.
     pub fn is_unused(&self) -> bool {
         matches!(self, Self::Unused)
     }
@@ -101,24 +129,86 @@ impl PortType {
 /// Records a use case of a range of ports.
 ///
 /// There may be multiple use cases for the same range of ports.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PortRangeInfo {
+    /// The port or range of ports.
     pub number: RangeInclusive<u16>,
+    /// How TCP is used.
     pub tcp_type: PortType,
+    /// How UDP is used.
     pub udp_type: PortType,
+    /// How SCTP is used.
     pub sctp_type: PortType,
+    /// How DCCP is used.
     pub dccp_type: PortType,
+    /// The description of the use case.
     pub rich_description: Vec<RichTextSpan>,
+    /// Service names of this use case, with the canonical name first.
+    ///
+    /// Not available from Wikipedia.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub service_names: Vec<String>,
+    /// Registration details, only available from the IANA registry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registration: Option<IanaRegistration>,
 }
 impl PortRangeInfo {
     /// Whether this port matches the user's requested port and should be shown.
     pub fn matches_port(&self, lookup: PortSelection) -> bool {
+        self.number.contains(&lookup.number) && self.supports_protocol(lookup.protocol)
+    }
+
+    /// Whether this port range overlaps with the user's requested port range
+    /// and should be shown.
+    pub fn matches_range(&self, lookup: &PortRangeSelection) -> bool {
+        let overlaps =
+            self.number.start() <= lookup.range.end() && lookup.range.start() <= self.number.end();
+        overlaps && self.supports_protocol(lookup.protocol)
+    }
+
+    /// The protocols this port is used for.
+    pub fn used_protocols(&self) -> Vec<SupportedProtocol> {
         use SupportedProtocol as P;
 
-        if !self.number.contains(&lookup.number) {
-            return false;
+        [
+            (P::Tcp, self.tcp_type),
+            (P::Udp, self.udp_type),
+            (P::Sctp, self.sctp_type),
+            (P::Dccp, self.dccp_type),
+        ]
+        .into_iter()
+        .filter(|(_, t)| !t.is_unused())
+        .map(|(p, _)| p)
+        .collect()
+    }
+
+    /// The port types for the specified protocol, or for all protocols if
+    /// unspecified.
+    pub fn types_for(&self, protocol: SupportedProtocol) -> Vec<PortType> {
+        use SupportedProtocol as P;
+
+        match protocol {
+            P::Any => vec![self.tcp_type, self.udp_type, self.sctp_type, self.dccp_type],
+            P::Tcp => vec![self.tcp_type],
+            P::Udp => vec![self.udp_type],
+            P::Sctp => vec![self.sctp_type],
+            P::Dccp => vec![self.dccp_type],
         }
-        match lookup.protocol {
+    }
+
+    /// Whether all uses of this port for the specified protocol are unofficial.
+    pub fn is_unofficial_only(&self, protocol: SupportedProtocol) -> bool {
+        self.types_for(protocol)
+            .iter()
+            .filter(|t| !t.is_unused())
+            .all(|t| *t == PortType::Unofficial)
+    }
+
+    /// Whether this port is used for the specified protocol.
+    pub fn supports_protocol(&self, protocol: SupportedProtocol) -> bool {
+        use SupportedProtocol as P;
+
+        match protocol {
             P::Any => true,
             P::Tcp => !self.tcp_type.is_unused(),
             P::Udp => !self.udp_type.is_unused(),
@@ -127,58 +217,168 @@ impl PortRangeInfo {
         }
     }
 
-    /// Check if the description contains the search term.
+    /// The displayed text of the description, excluding notes and references.
+    pub fn plain_description(&self) -> String {
+        self.rich_description
+            .iter()
+            .filter_map(RichTextSpan::normal_text)
+            .join("")
+    }
+
+    /// Check if the description matches the search query.
     ///
     /// This match is case-insensitive.
     pub fn matches_search(
         &self,
-        search: impl AsRef<str>,
+        search: &SearchQuery,
         include_links: bool,
         include_notes_and_references: bool,
     ) -> bool {
-        let search = search.as_ref().to_lowercase();
+        // the concatenated normal text is also checked, because a search term
+        // could ride on span boundaries
+        // e.g. `foo bar` should match `foo [bar](example.org) baz`
+        // regexes are case-insensitive, so they can match the lowercase text too
+        let text = self.plain_description().to_lowercase();
+        let matches_phrase = |phrase: &str| {
+            self.rich_description.iter().any(|span| {
+                span.matches_search(phrase, include_links, include_notes_and_references)
+            }) || text.contains(phrase)
+        };
+        let matches_regex = |regex: &Regex| {
+            self.rich_description
+                .iter()
+                .any(|span| span.matches_regex(regex, include_links, include_notes_and_references))
+                || regex.is_match(&text)
+        };
+        search.matches(matches_phrase, matches_regex)
+    }
+}
 
-        // matched if any individual span contains the search term
-        if self
-            .rich_description
-            .iter()
-            .any(|span| span.matches_search(&search, include_links, include_notes_and_references))
-        {
-            return true;
+/// User-specified filters for port use cases.
+///
+/// An empty list means no filtering on that criterion.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueryFilter {
+    /// Only accept use cases with any of these port types.
+    pub types: Vec<PortType>,
+    /// Only accept use cases for this protocol.
+    pub protocol: SupportedProtocol,
+    /// Only accept use cases with ports in any of these categories.
+    pub categories: Vec<PortCategory>,
+}
+impl QueryFilter {
+    /// Whether a port use case passes this filter.
+    ///
+    /// When both a port type and a protocol are specified, the port type is
+    /// only checked for that protocol.
+    pub fn accepts(&self, info: &PortRangeInfo) -> bool {
+        if !info.supports_protocol(self.protocol) {
+            return false;
         }
 
-        // matched if the concatenated normal text contains the search term
-        // this is necessary because a search term could ride on span boundaries
-        // e.g. `foo bar` should match `foo [bar](example.org) baz`
-        let text = self
-            .rich_description
-            .iter()
-            .filter_map(RichTextSpan::normal_text)
-            .map(|s| s.to_lowercase())
-            .join("");
-        if text.contains(&search) {
-            return true;
+        if !self.types.is_empty() {
+            let types = info.types_for(self.protocol);
+            if !types.iter().any(|t| self.types.contains(t)) {
+                return false;
+            }
+        }
+
+        if !self.categories.is_empty() {
+            let overlaps = |c: &PortCategory| {
+                let range = c.range();
+                range.start() <= info.number.end() && info.number.start() <= range.end()
+            };
+            if !self.categories.iter().any(overlaps) {
+                return false;
+            }
         }
 
-        false
+        true
+    }
+}
+
+/// User-specified parameters for finding free ports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FreePortQuery {
+    /// The range of ports to search in.
+    pub range: RangeInclusive<u16>,
+    /// The number of ports or blocks of ports to find.
+    pub count: usize,
+    /// The size of each block of ports.
+    pub block_size: u16,
+    /// Consider ports whose only known use cases are unofficial as free.
+    pub ignore_unofficial: bool,
+    /// Do not return blocks that cross a port category boundary.
+    pub within_category: bool,
+}
+
+/// Where the information in a [`PortDatabase`] came from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "name", rename_all = "kebab-case")]
+pub enum DataSource {
+    /// The Wikipedia page, at a specific revision.
+    Wikipedia {
+        /// The revision ID of the page.
+        revision: u64,
+    },
+    /// The IANA Service Name and Transport Protocol Port Number Registry.
+    Iana,
+    /// A local services file, typically `/etc/services`.
+    Services {
+        /// The path of the file.
+        path: PathBuf,
+    },
+    /// The Wikipedia page snapshot bundled into the binary, at a specific revision.
+    #[cfg(feature = "bundled-snapshot")]
+    BundledSnapshot {
+        /// The revision ID of the page.
+        revision: u64,
+    },
+}
+impl fmt::Display for DataSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wikipedia { revision } => write!(f, "Wikipedia (revision {revision})"),
+            Self::Iana => write!(f, "IANA registry"),
+            Self::Services { path } => write!(f, "services file {}", path.display()),
+            #[cfg(feature = "bundled-snapshot")]
+            Self::BundledSnapshot { revision } => {
+                write!(f, "bundled snapshot of Wikipedia (revision {revision})")
+            }
+        }
     }
 }
 
 /// Records all known use cases for all known ports.
-#[derive(Clone, Debug)]
-pub struct PortDatabase(pub Vec<PortRangeInfo>);
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PortDatabase {
+    /// Where the records came from.
+    pub source: DataSource,
+    /// All records, in the order of the source.
+    pub ports: Vec<PortRangeInfo>,
+    /// The URLs that links in descriptions are resolved against.
+    ///
+    /// Not cached with the records, since they can be overridden on each run.
+    #[serde(skip)]
+    pub urls: SourceUrls,
+}
 impl PortDatabase {
+    /// Look up the use cases of a port accepted by the filter.
     pub fn lookup(
         &self,
         lookup: PortSelection,
+        filter: &QueryFilter,
         show_links: bool,
         show_notes_and_references: bool,
     ) -> PortLookupOutput<'_> {
-        let (use_cases, _accumulated_idx) = self.0.iter().filter(|p| p.matches_port(lookup)).fold(
-            (vec![], 1),
-            |(mut list, mut link_idx), p| {
+        let (use_cases, _accumulated_idx) = self
+            .ports
+            .iter()
+            .filter(|p| p.matches_port(lookup) && filter.accepts(p))
+            .fold((vec![], 1), |(mut list, mut link_idx), p| {
                 let use_case = PortUseCase::from_with_options(
                     p,
+                    &self.urls,
                     show_links.then_some(link_idx),
                     show_notes_and_references,
                 );
@@ -186,8 +386,7 @@ impl PortDatabase {
                 link_idx += use_case.link_count();
                 list.push(use_case);
                 (list, link_idx)
-            },
-        );
+            });
 
         // note that these use cases may come from different port ranges
         // because ranges may overlap
@@ -201,30 +400,391 @@ impl PortDatabase {
                 use_cases,
             })
         };
-        PortLookupOutput { lookup, matched }
+        PortLookupOutput { source: &self.source, lookup, matched }
+    }
+
+    /// Look up the use cases accepted by the filter that overlap a range of ports.
+    pub fn range_lookup(
+        &self,
+        lookup: PortRangeSelection,
+        filter: &QueryFilter,
+        show_links: bool,
+        show_notes_and_references: bool,
+    ) -> RangeLookupOutput<'_> {
+        let matched = self.group_by_port_range(
+            self.ports
+                .iter()
+                .filter(|p| p.matches_range(&lookup) && filter.accepts(p)),
+            show_links,
+            show_notes_and_references,
+        );
+
+        RangeLookupOutput { source: &self.source, lookup, matched }
+    }
+
+    /// Look up all ports that a resolved service is registered on.
+    pub fn service_lookup(
+        &self,
+        service: impl AsRef<str>,
+        resolved: &IndexedService,
+        filter: &QueryFilter,
+        show_links: bool,
+        show_notes_and_references: bool,
+    ) -> ServiceLookupOutput<'_> {
+        let results = resolved
+            .ports
+            .iter()
+            .map(|port| match port {
+                ServicePort::Port(port) => self
+                    .lookup(*port, filter, show_links, show_notes_and_references)
+                    .into(),
+                ServicePort::Range(range) => self
+                    .range_lookup(range.clone(), filter, show_links, show_notes_and_references)
+                    .into(),
+            })
+            .collect::<Vec<Output>>();
+
+        ServiceLookupOutput {
+            source: &self.source,
+            service: service.as_ref().to_owned(),
+            canonical: resolved.canonical.clone(),
+            results,
+        }
+    }
+
+    /// Answer a user query.
+    ///
+    /// Search terms that are the exact name of a known service are looked up
+    /// as a service, otherwise port descriptions are searched.
+    pub fn query(
+        &self,
+        query: UserQuery,
+        service_index: &ServiceIndex,
+        filter: &QueryFilter,
+        show_links: bool,
+        show_notes_and_references: bool,
+    ) -> Output<'_> {
+        match query {
+            UserQuery::PortLookup(port) => self
+                .lookup(port, filter, show_links, show_notes_and_references)
+                .into(),
+            UserQuery::RangeLookup(range) => self
+                .range_lookup(range, filter, show_links, show_notes_and_references)
+                .into(),
+            UserQuery::Search(search) => match service_index.resolve(search.as_str()) {
+                Some(service) => self
+                    .service_lookup(
+                        search.as_str(),
+                        service,
+                        filter,
+                        show_links,
+                        show_notes_and_references,
+                    )
+                    .into(),
+                None => self
+                    .search(&search, filter, show_links, show_notes_and_references)
+                    .into(),
+            },
+        }
+    }
+
+    /// Find the most authoritative use case of a port.
+    ///
+    /// Use cases assigned by IANA are preferred over unofficial ones.
+    pub fn best_use_case(
+        &self,
+        lookup: PortSelection,
+        filter: &QueryFilter,
+        show_links: bool,
+        show_notes_and_references: bool,
+    ) -> Option<PortUseCase<'_>> {
+        let rank = |t: &PortType| match t {
+            PortType::Yes => 0,
+            PortType::Assigned => 1,
+            PortType::Unofficial => 2,
+            PortType::Reserved => 3,
+            PortType::No => 4,
+            PortType::Unused => 5,
+        };
+
+        let best = self
+            .ports
+            .iter()
+            .filter(|p| p.matches_port(lookup) && filter.accepts(p))
+            .min_by_key(|p| p.types_for(lookup.protocol).iter().map(rank).min())?;
+        let use_case = PortUseCase::from_with_options(
+            best,
+            &self.urls,
+            show_links.then_some(1),
+            show_notes_and_references,
+        );
+        Some(use_case)
     }
 
+    /// Find ports or contiguous blocks of ports that have no use cases
+    /// accepted by the filter.
+    pub fn find_free(&self, query: FreePortQuery, filter: &QueryFilter) -> FreePortsOutput<'_> {
+        let FreePortQuery {
+            ref range,
+            count,
+            block_size,
+            ignore_unofficial,
+            within_category,
+        } = query;
+
+        // mark occupied ports
+        let mut occupied = vec![false; u16::MAX as usize + 1];
+        let occupying = self
+            .ports
+            .iter()
+            .filter(|p| filter.accepts(p))
+            .filter(|p| !(ignore_unofficial && p.is_unofficial_only(filter.protocol)));
+        for info in occupying {
+            let (start, end) = (*info.number.start() as usize, *info.number.end() as usize);
+            occupied[start..=end].fill(true);
+        }
+
+        let eligible = |port: u16| {
+            let category = PortCategory::from(port);
+            !occupied[port as usize]
+                && (filter.categories.is_empty() || filter.categories.contains(&category))
+        };
+
+        // greedily take the first free blocks
+        let mut free = vec![];
+        let mut start = *range.start() as u32;
+        let range_end = *range.end() as u32;
+        while free.len() < count && start + block_size as u32 - 1 <= range_end {
+            let end = start + block_size as u32 - 1;
+            let blocker = (start..=end).rev().find(|&p| !eligible(p as u16));
+            let crosses_category = within_category
+                && PortCategory::from(start as u16) != PortCategory::from(end as u16);
+            match blocker {
+                // skip past the port that blocks this block
+                Some(p) => start = p + 1,
+                None if crosses_category => {
+                    start = *PortCategory::from(start as u16).range().end() as u32 + 1;
+                }
+                None => {
+                    free.push(start as u16..=end as u16);
+                    start = end + 1;
+                }
+            }
+        }
+
+        FreePortsOutput {
+            source: &self.source,
+            range: range.clone(),
+            protocol: filter.protocol,
+            requested: count,
+            free,
+        }
+    }
+
+    /// Search the descriptions and service names of all use cases accepted by the filter.
     pub fn search(
         &self,
-        search: impl AsRef<str>,
+        search: &SearchQuery,
+        filter: &QueryFilter,
         show_links: bool,
         show_notes_and_references: bool,
     ) -> SearchOutput<'_> {
-        let search = search.as_ref().to_owned();
+        let matched = self.group_by_port_range(
+            self.ports
+                .iter()
+                .filter(|p| filter.accepts(p))
+                .filter(|p| p.matches_search(search, show_links, show_notes_and_references)),
+            show_links,
+            show_notes_and_references,
+        );
+
+        SearchOutput {
+            source: &self.source,
+            search: search.to_string(),
+            matched,
+        }
+    }
 
-        let (matched, _accumulated_idx) = self
-            .0
+    /// The number of description spans that could not be parsed.
+    pub fn unknown_span_count(&self) -> usize {
+        self.ports
             .iter()
-            .filter(|p| p.matches_search(&search, show_links, show_notes_and_references))
+            .flat_map(|p| &p.rich_description)
+            .filter(|span| matches!(span, RichTextSpan::Unknown { .. }))
+            .count()
+    }
+
+    /// Compare the use cases in this database with those in a newer one.
+    ///
+    /// Use cases are matched by their port range and description. Unmatched
+    /// use cases of the same port range are considered modified, in order of
+    /// appearance.
+    pub fn diff<'a>(
+        &'a self,
+        newer: &'a PortDatabase,
+        filter: &QueryFilter,
+        show_links: bool,
+        show_notes_and_references: bool,
+    ) -> DiffOutput<'a> {
+        let mut link_idx = 1;
+        let changes = diff_use_cases(
+            Some(self),
+            newer,
+            |p| filter.accepts(p),
+            show_links.then_some(&mut link_idx),
+            show_notes_and_references,
+        );
+
+        DiffOutput {
+            old: &self.source,
+            new: &newer.source,
+            changes,
+        }
+    }
+
+    /// Trace the use cases of a port through a series of databases, which
+    /// should be sorted from oldest to newest.
+    ///
+    /// Only databases in which the use cases changed are included in the timeline.
+    pub fn history<'a>(
+        dbs: &'a [PortDatabase],
+        lookup: PortSelection,
+        filter: &QueryFilter,
+        show_links: bool,
+        show_notes_and_references: bool,
+    ) -> HistoryOutput<'a> {
+        let mut link_idx = 1;
+        let mut timeline = vec![];
+        let mut previous = None;
+        for db in dbs {
+            let changes = diff_use_cases(
+                previous,
+                db,
+                |p| p.matches_port(lookup) && filter.accepts(p),
+                show_links.then_some(&mut link_idx),
+                show_notes_and_references,
+            );
+            if !changes.is_empty() {
+                timeline.push(HistoryEntry { source: &db.source, changes });
+            }
+            previous = Some(db);
+        }
+
+        HistoryOutput {
+            lookup,
+            revision_count: dbs.len(),
+            timeline,
+        }
+    }
+
+    /// Look up the port of each listening socket in some databases.
+    pub fn lookup_sockets<'a>(
+        dbs: &'a [PortDatabase],
+        sockets: Vec<ListeningSocket>,
+        filter: &QueryFilter,
+        show_links: bool,
+        show_notes_and_references: bool,
+    ) -> LocalOutput<'a> {
+        let sockets = sockets
+            .into_iter()
+            .map(|socket| {
+                let lookup = PortSelection {
+                    number: socket.port,
+                    protocol: socket.protocol,
+                };
+                let (pid, command) = socket.owner.map(|o| (o.pid, o.command)).unzip();
+                LocalSocketOutput {
+                    protocol: socket.protocol,
+                    address: socket.address,
+                    port: socket.port,
+                    pid,
+                    command: command.flatten(),
+                    lookups: dbs
+                        .iter()
+                        .map(|db| db.lookup(lookup, filter, show_links, show_notes_and_references))
+                        .collect(),
+                }
+            })
+            .collect();
+        LocalOutput { sockets }
+    }
+
+    /// Look up the open ports of each host in an nmap report in some databases.
+    pub fn lookup_nmap_report<'a>(
+        dbs: &'a [PortDatabase],
+        hosts: Vec<NmapHost>,
+        filter: &QueryFilter,
+        show_links: bool,
+        show_notes_and_references: bool,
+    ) -> NmapOutput<'a> {
+        let lookup_port = |port: NmapPort| {
+            let lookup = PortSelection {
+                number: port.number,
+                protocol: port.protocol,
+            };
+            NmapPortOutput {
+                protocol: port.protocol,
+                port: port.number,
+                service: port.service,
+                lookups: dbs
+                    .iter()
+                    .map(|db| db.lookup(lookup, filter, show_links, show_notes_and_references))
+                    .collect(),
+            }
+        };
+        let hosts = hosts
+            .into_iter()
+            .map(|host| NmapHostOutput {
+                address: host.address,
+                hostname: host.hostname,
+                ports: host.ports.into_iter().map(lookup_port).collect(),
+            })
+            .collect();
+        NmapOutput { hosts }
+    }
+
+    /// Annotate each port found in a line with its best use case in the first
+    /// database that knows of it.
+    pub fn annotate_line<'a>(
+        dbs: &'a [PortDatabase],
+        line: String,
+        service_index: &ServiceIndex,
+        filter: &QueryFilter,
+        show_links: bool,
+        show_notes_and_references: bool,
+    ) -> AnnotatedLineOutput<'a> {
+        let annotations = find_ports(&line, service_index)
+            .into_iter()
+            .map(|port| {
+                let best = dbs.iter().find_map(|db| {
+                    db.best_use_case(port, filter, show_links, show_notes_and_references)
+                        .map(|use_case| (&db.source, use_case))
+                });
+                let (source, use_case) = best.unzip();
+                PortAnnotation { port, source, use_case }
+            })
+            .collect();
+        AnnotatedLineOutput { line, annotations }
+    }
+
+    /// Group port use cases by their port range, sorted by the start of each range.
+    fn group_by_port_range<'a>(
+        &'a self,
+        infos: impl Iterator<Item = &'a PortRangeInfo>,
+        show_links: bool,
+        show_notes_and_references: bool,
+    ) -> Vec<MatchedPort<'a>> {
+        let (matched, _accumulated_idx) = infos
             .into_group_map_by(|p| &p.number)
             .into_iter()
-            .sorted_by_key(|(n, _)| n.start())
+            .sorted_by_key(|(n, _)| (n.start(), n.end()))
             .fold((vec![], 1), |(mut list, link_idx), (n, info)| {
                 let (use_cases, accumulated_link_idx) =
                     info.into_iter()
                         .fold((vec![], link_idx), |(mut list, mut link_idx), p| {
                             let use_case = PortUseCase::from_with_options(
                                 p,
+                                &self.urls,
                                 show_links.then_some(link_idx),
                                 show_notes_and_references,
                             );
@@ -239,6 +799,200 @@ impl PortDatabase {
                 (list, accumulated_link_idx)
             });
 
-        SearchOutput { search, matched }
+        matched
+    }
+}
+
+/// Compare the use cases accepted by a predicate in two databases.
+///
+/// An absent old database has no use cases.
+/// See [`PortDatabase::diff`] for how use cases are matched.
+///
+/// `link_idx` expects the starting index if links are to be shown, and is
+/// advanced past the links of the changed use cases.
+fn diff_use_cases<'a>(
+    old_db: Option<&'a PortDatabase>,
+    new_db: &'a PortDatabase,
+    accepts: impl Fn(&PortRangeInfo) -> bool,
+    mut link_idx: Option<&mut usize>,
+    show_notes_and_references: bool,
+) -> Vec<PortChange<'a>> {
+    let group = |db: &'a PortDatabase| {
+        db.ports
+            .iter()
+            .filter(|p| accepts(p))
+            .into_group_map_by(|p| (*p.number.start(), *p.number.end()))
+    };
+    let mut old_groups = old_db.map(group).unwrap_or_default();
+    let mut new_groups = group(new_db);
+    let ranges = old_groups
+        .keys()
+        .chain(new_groups.keys())
+        .copied()
+        .unique()
+        .sorted()
+        .collect_vec();
+
+    // links are resolved against the URLs of the database each use case is from
+    let old_urls = old_db.map_or(&new_db.urls, |db| &db.urls);
+    let new_urls = &new_db.urls;
+    let mut use_case = |p: &'a PortRangeInfo, urls: &SourceUrls| {
+        let use_case = PortUseCase::from_with_options(
+            p,
+            urls,
+            link_idx.as_deref().copied(),
+            show_notes_and_references,
+        );
+        if let Some(idx) = link_idx.as_deref_mut() {
+            *idx += use_case.link_count();
+        }
+        use_case
+    };
+
+    let mut changes = vec![];
+    for (start, end) in ranges {
+        let number = start..=end;
+        let mut old_list = old_groups.remove(&(start, end)).unwrap_or_default();
+        let mut new_list = new_groups.remove(&(start, end)).unwrap_or_default();
+
+        // pair up use cases with identical descriptions first
+        let mut paired = vec![];
+        old_list.retain(|old| {
+            let description = old.plain_description();
+            match new_list
+                .iter()
+                .position(|new| new.plain_description() == description)
+            {
+                Some(idx) => {
+                    paired.push((*old, new_list.remove(idx)));
+                    false
+                }
+                None => true,
+            }
+        });
+        // then pair up the remaining ones in order
+        let remaining = old_list.len().min(new_list.len());
+        let removed = old_list.split_off(remaining);
+        let added = new_list.split_off(remaining);
+        paired.extend(old_list.into_iter().zip(new_list));
+
+        for (old, new) in paired {
+            let description_changed = old.plain_description() != new.plain_description();
+            let types_changed =
+                old.types_for(SupportedProtocol::Any) != new.types_for(SupportedProtocol::Any);
+            if description_changed || types_changed {
+                changes.push(PortChange::Modified {
+                    number: number.clone(),
+                    old: use_case(old, old_urls),
+                    new: use_case(new, new_urls),
+                    description_changed,
+                    types_changed,
+                });
+            }
+        }
+        for old in removed {
+            changes.push(PortChange::Removed {
+                number: number.clone(),
+                use_case: use_case(old, old_urls),
+            });
+        }
+        for new in added {
+            changes.push(PortChange::Added {
+                number: number.clone(),
+                use_case: use_case(new, new_urls),
+            });
+        }
+    }
+
+    changes
+}
+
+/// A service resolved from its name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedService {
+    /// The canonical name of the service.
+    pub canonical: String,
+    /// The ports this service is registered on.
+    pub ports: Vec<ServicePort>,
+}
+
+/// A port or port range that a service is registered on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServicePort {
+    /// A single port.
+    Port(PortSelection),
+    /// A port range.
+    Range(PortRangeSelection),
+}
+
+/// A reverse index from service names and aliases to the ports they are
+/// registered on.
+///
+/// Names are matched case-insensitively.
+#[derive(Clone, Debug, Default)]
+pub struct ServiceIndex(HashMap<String, IndexedService>);
+impl ServiceIndex {
+    /// Build the index from the service names in some databases.
+    pub fn build<'a>(dbs: impl IntoIterator<Item = &'a PortDatabase>) -> Self {
+        /// Protocols of each port range.
+        type PortProtocols = Vec<(RangeInclusive<u16>, Vec<SupportedProtocol>)>;
+
+        // name -> (canonical name, protocols of each port range)
+        let mut map: HashMap<String, (String, PortProtocols)> = HashMap::new();
+
+        let infos = dbs.into_iter().flat_map(|db| &db.ports);
+        for info in infos {
+            let Some(canonical) = info.service_names.first() else {
+                continue;
+            };
+            for name in &info.service_names {
+                let (_, ports) = map
+                    .entry(name.to_lowercase())
+                    .or_insert_with(|| (canonical.clone(), vec![]));
+                let protocols = match ports.iter_mut().find(|(range, _)| range == &info.number) {
+                    Some((_, protocols)) => protocols,
+                    None => {
+                        ports.push((info.number.clone(), vec![]));
+                        &mut ports.last_mut().unwrap().1
+                    }
+                };
+                protocols.extend(info.used_protocols());
+            }
+        }
+
+        let index = map
+            .into_iter()
+            .map(|(name, (canonical, ports))| {
+                let ports = ports
+                    .into_iter()
+                    .sorted_by_key(|(range, _)| (*range.start(), *range.end()))
+                    .map(|(range, protocols)| {
+                        let protocols = protocols.into_iter().unique().collect_vec();
+                        let protocol = match protocols.as_slice() {
+                            [p] => *p,
+                            _ => SupportedProtocol::Any,
+                        };
+                        if range.start() == range.end() {
+                            let number = *range.start();
+                            ServicePort::Port(PortSelection { number, protocol })
+                        } else {
+                            ServicePort::Range(PortRangeSelection { range, protocol })
+                        }
+                    })
+                    .collect();
+                (name, IndexedService { canonical, ports })
+            })
+            .collect();
+        Self(index)
+    }
+
+    /// Whether the index contains no service names.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Resolve a service by its exact name or alias.
+    pub fn resolve(&self, name: impl AsRef<str>) -> Option<&IndexedService> {
+        self.0.get(&name.as_ref().trim().to_lowercase())
     }
 }
@@ -0,0 +1,486 @@
+//! A full-screen browser for the port table.
+
+use color_eyre::eyre::{bail, Context};
+use itertools::Itertools;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span, Text},
+    widgets::{Block, Cell, Paragraph, Row, Table, TableState, Wrap},
+    DefaultTerminal, Frame,
+};
+
+use crate::{
+    display::{port_type_color, render_rich_text, RenderedRichText, StyledSegment},
+    query::PortRangeSelection,
+    search::SearchQuery,
+    store::{PortDatabase, PortRangeInfo, PortType, QueryFilter},
+};
+
+/// How many rows page up and page down move by.
+const PAGE_SIZE: usize = 20;
+
+/// The state of the port table browser.
+#[derive(Debug)]
+pub struct Browser<'a> {
+    dbs: &'a [PortDatabase],
+    filter: QueryFilter,
+    range: Option<PortRangeSelection>,
+    show_links: bool,
+    show_notes_and_references: bool,
+
+    /// The index of the database being browsed.
+    db_idx: usize,
+    search: String,
+    /// Why the search term failed to parse, if it did.
+    search_error: Option<String>,
+    /// Whether key presses go to the search box.
+    searching: bool,
+    /// Use cases that pass the filter, range and search.
+    matched: Vec<&'a PortRangeInfo>,
+    table_state: TableState,
+}
+impl<'a> Browser<'a> {
+    /// Create a browser for the loaded databases.
+    ///
+    /// Only use cases in the range are listed, if one is specified.
+    /// Search matches links and notes and references according to the options.
+    pub fn new(
+        dbs: &'a [PortDatabase],
+        filter: QueryFilter,
+        range: Option<PortRangeSelection>,
+        show_links: bool,
+        show_notes_and_references: bool,
+    ) -> Self {
+        let mut browser = Self {
+            dbs,
+            filter,
+            range,
+            show_links,
+            show_notes_and_references,
+            db_idx: 0,
+            search: String::new(),
+            search_error: None,
+            searching: false,
+            matched: vec![],
+            table_state: TableState::default(),
+        };
+        browser.update_matched();
+        browser
+    }
+
+    /// Take over the terminal until the user quits.
+    ///
+    /// This function is blocking.
+    pub fn run(mut self) -> color_eyre::Result<()> {
+        if self.dbs.is_empty() {
+            bail!("No data source is loaded");
+        }
+
+        let mut terminal = ratatui::init();
+        let res = self.run_loop(&mut terminal);
+        ratatui::restore();
+        res
+    }
+
+    fn run_loop(&mut self, terminal: &mut DefaultTerminal) -> color_eyre::Result<()> {
+        loop {
+            terminal
+                .draw(|frame| self.draw(frame))
+                .wrap_err("Failed to draw to terminal")?;
+
+            let Event::Key(key) = event::read().wrap_err("Failed to read terminal event")? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let quit = if self.searching {
+                self.handle_search_key(key)
+            } else {
+                self.handle_browse_key(key)
+            };
+            if quit {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Handle a key press while typing in the search box.
+    ///
+    /// Returns whether the user wants to quit.
+    fn handle_search_key(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return true,
+            KeyCode::Enter => self.searching = false,
+            KeyCode::Esc => {
+                self.searching = false;
+                self.search.clear();
+                self.update_matched();
+            }
+            KeyCode::Backspace => {
+                self.search.pop();
+                self.update_matched();
+            }
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.search.push(c);
+                self.update_matched();
+            }
+            KeyCode::Up | KeyCode::Down => {
+                self.searching = false;
+                self.handle_browse_key(key);
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Handle a key press while browsing the table.
+    ///
+    /// Returns whether the user wants to quit.
+    fn handle_browse_key(&mut self, key: KeyEvent) -> bool {
+        let last = self.matched.len().saturating_sub(1);
+        let selected = self.table_state.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return true,
+            KeyCode::Char('/') => self.searching = true,
+            KeyCode::Down | KeyCode::Char('j') => self.select(selected.saturating_add(1).min(last)),
+            KeyCode::Up | KeyCode::Char('k') => self.select(selected.saturating_sub(1)),
+            KeyCode::PageDown => self.select(selected.saturating_add(PAGE_SIZE).min(last)),
+            KeyCode::PageUp => self.select(selected.saturating_sub(PAGE_SIZE)),
+            KeyCode::Home | KeyCode::Char('g') => self.select(0),
+            KeyCode::End | KeyCode::Char('G') => self.select(last),
+            KeyCode::Tab => {
+                self.db_idx = (self.db_idx + 1) % self.dbs.len();
+                self.update_matched();
+            }
+            _ => {}
+        }
+        false
+    }
+
+    fn select(&mut self, idx: usize) {
+        let selected = (!self.matched.is_empty()).then_some(idx);
+        self.table_state.select(selected);
+    }
+
+    /// Recompute the listed use cases, e.g. after the search term changed.
+    fn update_matched(&mut self) {
+        // an invalid search matches nothing, until the user finishes typing
+        let search = match self.search.trim() {
+            "" => Ok(None),
+            search => search.parse::<SearchQuery>().map(Some),
+        };
+        self.search_error = search.as_ref().err().cloned();
+
+        let db = &self.dbs[self.db_idx];
+        self.matched = db
+            .ports
+            .iter()
+            .filter(|p| self.filter.accepts(p))
+            .filter(|p| {
+                self.range
+                    .as_ref()
+                    .is_none_or(|range| p.matches_range(range))
+            })
+            .filter(|p| match &search {
+                Ok(None) => true,
+                Ok(Some(search)) => {
+                    p.matches_search(search, self.show_links, self.show_notes_and_references)
+                }
+                Err(_) => false,
+            })
+            .collect();
+        self.select(0);
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [search_area, table_area, detail_area, help_area] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Percentage(55),
+            Constraint::Fill(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        self.draw_search(frame, search_area);
+        self.draw_table(frame, table_area);
+        self.draw_detail(frame, detail_area);
+
+        let help = if self.searching {
+            "Enter: done  Esc: clear search  ↑↓: browse"
+        } else {
+            "↑↓/jk: move  PgUp/PgDn  g/G: first/last  /: search  Tab: next source  q: quit"
+        };
+        frame.render_widget(Line::from(help).dark_gray(), help_area);
+    }
+
+    fn draw_search(&self, frame: &mut Frame, area: Rect) {
+        let mut block = Block::bordered().title(" Search ");
+        if self.searching {
+            block = block.yellow();
+        }
+        if let Some(err) = &self.search_error {
+            block = block.title(Line::from(format!(" {err} ")).red());
+        }
+        let cursor = if self.searching { "█" } else { "" };
+        let search = Paragraph::new(format!("{}{cursor}", self.search)).block(block);
+        frame.render_widget(search, area);
+    }
+
+    fn draw_table(&mut self, frame: &mut Frame, area: Rect) {
+        let header = ["Port", "TCP", "UDP", "SCTP", "DCCP", "Description"]
+            .into_iter()
+            .map(|title| Cell::from(title).bold())
+            .collect::<Row>();
+        let rows = self.matched.iter().map(|p| {
+            let types = [p.tcp_type, p.udp_type, p.sctp_type, p.dccp_type]
+                .map(|t| Cell::from(t.to_string()).style(port_type_style(t)));
+            [Cell::from(format_range(p))]
+                .into_iter()
+                .chain(types)
+                .chain([Cell::from(p.plain_description())])
+                .collect::<Row>()
+        });
+        let widths = [
+            Constraint::Length(11),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Fill(1),
+        ];
+
+        let db = &self.dbs[self.db_idx];
+        let title = format!(" {} · {} use cases ", db.source, self.matched.len());
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::bordered().title(title))
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, area, &mut self.table_state);
+    }
+
+    fn draw_detail(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(" Details ");
+        let selected = self
+            .table_state
+            .selected()
+            .and_then(|idx| self.matched.get(idx));
+        let Some(info) = selected else {
+            frame.render_widget(Paragraph::new("No matching use case").block(block), area);
+            return;
+        };
+
+        let mut lines = vec![];
+        lines.push(Line::from(vec!["Port: ".bold(), format_range(info).into()]));
+        let protocols = [
+            ("TCP", info.tcp_type),
+            ("UDP", info.udp_type),
+            ("SCTP", info.sctp_type),
+            ("DCCP", info.dccp_type),
+        ]
+        .into_iter()
+        .filter(|(_, t)| !t.is_unused())
+        .map(|(label, t)| {
+            vec![
+                format!("{label}: ").into(),
+                Span::styled(t.to_string(), port_type_style(t)),
+            ]
+        });
+        let protocols = Itertools::intersperse(protocols, vec![", ".into()]).flatten();
+        lines.push(Line::from_iter(
+            ["Protocols: ".bold()].into_iter().chain(protocols),
+        ));
+        if !info.service_names.is_empty() {
+            lines.push(Line::from(vec![
+                "Services: ".bold(),
+                info.service_names.join(", ").into(),
+            ]));
+        }
+        lines.push(Line::default());
+
+        let RenderedRichText { description, links, notes_and_refs } = render_rich_text(
+            &info.rich_description,
+            &self.dbs[self.db_idx].urls,
+            self.show_links.then_some(1),
+            self.show_notes_and_references,
+        );
+        lines.push(Line::from_iter(description.into_iter().map(segment_span)));
+        for (title, list) in [("Links", links), ("Notes and references", notes_and_refs)] {
+            if list.is_empty() {
+                continue;
+            }
+            lines.push(Line::default());
+            lines.push(Line::from(format!("{title}:")).bold());
+            lines.extend(list.into_iter().map(|(tag, url)| {
+                Line::from(vec![
+                    "    ".into(),
+                    segment_span(tag),
+                    ": ".into(),
+                    url.into(),
+                ])
+            }));
+        }
+
+        let detail = Paragraph::new(Text::from(lines))
+            .block(block)
+            .wrap(Wrap { trim: false });
+        frame.render_widget(detail, area);
+    }
+}
+
+/// Format the port number or range of a use case.
+fn format_range(info: &PortRangeInfo) -> String {
+    let (start, end) = (info.number.start(), info.number.end());
+    if start == end {
+        start.to_string()
+    } else {
+        format!("{start}-{end}")
+    }
+}
+
+/// The style of a port type, in the same colours as text output.
+fn port_type_style(port_type: PortType) -> Style {
+    Style::new().fg(terminal_color(port_type_color(port_type)))
+}
+
+/// A segment of a rendered description, in the same style as text output.
+fn segment_span(segment: StyledSegment) -> Span<'static> {
+    let StyledSegment { text, color, url } = segment;
+    let mut style = Style::new();
+    if let Some(color) = color {
+        style = style.fg(terminal_color(color));
+    }
+    if url.is_some() {
+        style = style.italic();
+    }
+    Span::styled(text, style)
+}
+
+/// Convert a colour of text output.
+fn terminal_color(color: yansi::Color) -> Color {
+    use yansi::Color as C;
+
+    match color {
+        C::Primary => Color::Reset,
+        C::Fixed(n) => Color::Indexed(n),
+        C::Rgb(r, g, b) => Color::Rgb(r, g, b),
+        C::Black => Color::Black,
+        C::Red => Color::Red,
+        C::Green => Color::Green,
+        C::Yellow => Color::Yellow,
+        C::Blue => Color::Blue,
+        C::Magenta => Color::Magenta,
+        C::Cyan => Color::Cyan,
+        C::White => Color::Gray,
+        C::BrightBlack => Color::DarkGray,
+        C::BrightRed => Color::LightRed,
+        C::BrightGreen => Color::LightGreen,
+        C::BrightYellow => Color::LightYellow,
+        C::BrightBlue => Color::LightBlue,
+        C::BrightMagenta => Color::LightMagenta,
+        C::BrightCyan => Color::LightCyan,
+        C::BrightWhite => Color::White,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::RangeInclusive;
+
+    use super::*;
+    use crate::{parse::RichTextSpan, store::DataSource};
+
+    fn info(number: RangeInclusive<u16>, tcp_type: PortType, description: &str) -> PortRangeInfo {
+        PortRangeInfo {
+            number,
+            tcp_type,
+            udp_type: PortType::Unused,
+            sctp_type: PortType::Unused,
+            dccp_type: PortType::Unused,
+            rich_description: vec![RichTextSpan::Text { text: description.into() }],
+            service_names: vec![],
+            registration: None,
+        }
+    }
+
+    fn dbs() -> Vec<PortDatabase> {
+        let ports = vec![
+            info(20..=21, PortType::Yes, "File Transfer Protocol"),
+            info(22..=22, PortType::Yes, "Secure Shell"),
+            info(23..=23, PortType::Unofficial, "Telnet over a secure shell"),
+            info(80..=80, PortType::Yes, "Hypertext Transfer Protocol"),
+        ];
+        vec![PortDatabase {
+            source: DataSource::Wikipedia { revision: 1 },
+            ports,
+            urls: Default::default(),
+        }]
+    }
+
+    fn matched_ports(browser: &Browser) -> Vec<String> {
+        browser.matched.iter().map(|p| format_range(p)).collect()
+    }
+
+    fn type_search(browser: &mut Browser, search: &str) {
+        browser.handle_browse_key(KeyEvent::from(KeyCode::Char('/')));
+        for c in search.chars() {
+            browser.handle_search_key(KeyEvent::from(KeyCode::Char(c)));
+        }
+    }
+
+    #[test]
+    fn formats_single_port_and_range() {
+        assert_eq!(format_range(&info(22..=22, PortType::Yes, "")), "22");
+        assert_eq!(format_range(&info(20..=21, PortType::Yes, "")), "20-21");
+    }
+
+    #[test]
+    fn lists_everything_by_default() {
+        let dbs = dbs();
+        let browser = Browser::new(&dbs, QueryFilter::default(), None, false, false);
+        assert_eq!(matched_ports(&browser), ["20-21", "22", "23", "80"]);
+        assert_eq!(browser.table_state.selected(), Some(0));
+    }
+
+    #[test]
+    fn combines_filter_range_and_search() {
+        let dbs = dbs();
+        let filter = QueryFilter {
+            types: vec![PortType::Yes],
+            ..QueryFilter::default()
+        };
+        let range = "21-80".parse().unwrap();
+        let mut browser = Browser::new(&dbs, filter, Some(range), false, false);
+        assert_eq!(matched_ports(&browser), ["20-21", "22", "80"]);
+
+        type_search(&mut browser, "transfer");
+        assert_eq!(matched_ports(&browser), ["20-21", "80"]);
+    }
+
+    #[test]
+    fn invalid_search_matches_nothing() {
+        let dbs = dbs();
+        let mut browser = Browser::new(&dbs, QueryFilter::default(), None, false, false);
+        type_search(&mut browser, "shell OR");
+        assert!(browser.search_error.is_some());
+        assert!(browser.matched.is_empty());
+        assert_eq!(browser.table_state.selected(), None);
+
+        browser.handle_search_key(KeyEvent::from(KeyCode::Esc));
+        assert!(browser.search_error.is_none());
+        assert_eq!(browser.matched.len(), 4);
+    }
+
+    #[test]
+    fn ctrl_c_quits_while_searching() {
+        let dbs = dbs();
+        let mut browser = Browser::new(&dbs, QueryFilter::default(), None, false, false);
+        type_search(&mut browser, "ssh");
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert!(browser.handle_search_key(ctrl_c));
+        assert_eq!(browser.search, "ssh");
+    }
+}